@@ -0,0 +1,25 @@
+MEMORY
+{
+  /* NOTE K = KiBi = 1024 bytes */
+  /* 0x08003800 - 0x08003FFF holds the metadata of the slots, which follow
+     (see `valuelinediscovery/src/update.rs`) */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 14K
+  /* the first 256 bytes of RAM are kept across resets (see
+     `valuelinediscovery/src/fault.rs`) */
+  NOINIT : ORIGIN = 0x20000000, LENGTH = 256
+  RAM   : ORIGIN = 0x20000100, LENGTH = 8K - 256
+}
+
+/* This is where the call stack will be allocated. */
+/* The stack is of the full descending type. */
+/* NOTE Do NOT modify `_stack_start` unless you know what you are doing */
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
+
+/* Same as `valuelinediscovery/noinit.x` */
+SECTIONS
+{
+  .noinit (NOLOAD) :
+  {
+    *(.noinit .noinit.*);
+  } > NOINIT
+}
@@ -0,0 +1,247 @@
+//! Application images in the update slots
+
+use core::{ptr, slice};
+
+use cast::{u16, u32, usize};
+use dsc::crc;
+use dsc::flash::{self, Flash};
+use dsc::update::{self, Image, Slot};
+use stm32f100::interrupt::Interrupt;
+use stm32f100::{Crc, Rcc, NVIC, RCC, SCB};
+
+// Initial stack pointers that make sense
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = 0x2000_2000;
+
+/// An error
+pub enum Error {
+    /// Flash operation failed
+    Flash(flash::Error),
+    /// The image doesn't fit in a slot
+    TooLarge,
+    /// The image is linked to run from the other slot
+    WrongSlot,
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Error {
+        Error::Flash(error)
+    }
+}
+
+/// A verified application
+pub struct App {
+    vtor: u32,
+    sp: u32,
+    pc: u32,
+}
+
+/// Returns the application in `slot` if it's recorded there, it's not
+/// rejected and its CRC matches
+pub fn verify(slot: Slot, crc: &Crc, rcc: &Rcc) -> Option<App> {
+    let image = match slot.image() {
+        Some(image) => image,
+        None => return None,
+    };
+
+    if slot.is_rejected() || image.len < 8 ||
+        crc32(crc, rcc, slot.address(), image.len) != image.crc
+    {
+        return None;
+    }
+
+    let app = App {
+        vtor: slot.address(),
+        sp: word(slot.address()),
+        pc: word(slot.address() + 4),
+    };
+
+    // the entry point is a Thumb function in the image
+    if app.sp < RAM_START || app.sp > RAM_END || app.pc & 1 == 0 ||
+        !slot.contains(app.pc) ||
+        app.pc - slot.address() >= image.len
+    {
+        None
+    } else {
+        Some(app)
+    }
+}
+
+/// Returns the application to start, newest first
+///
+/// An image that isn't confirmed gets one of its `update::MAX_ATTEMPTS`
+/// boots counted; once they are used up it's rejected and the other slot
+/// gets its turn.
+///
+/// NOTE the flash must be unlocked
+pub fn select(flash: Flash, crc: &Crc, rcc: &Rcc) -> Option<App> {
+    for slot in &newest_first() {
+        let app = match verify(*slot, crc, rcc) {
+            Some(app) => app,
+            None => continue,
+        };
+
+        if slot.is_confirmed() || slot.count_attempt(flash).is_ok() {
+            return Some(app);
+        }
+
+        slot.reject(flash).ok();
+    }
+
+    None
+}
+
+/// Slot to write the next image to
+///
+/// That's the slot that isn't holding the image to fall back to: the newest
+/// confirmed one, else the newest one that can still boot.
+pub fn target(crc: &Crc, rcc: &Rcc) -> Slot {
+    let slots = newest_first();
+
+    let keep = slots
+        .iter()
+        .find(|slot| slot.is_confirmed() && verify(**slot, crc, rcc).is_some())
+        .or_else(
+            || slots.iter().find(|slot| verify(**slot, crc, rcc).is_some()),
+        );
+
+    match keep {
+        Some(slot) => slot.other(),
+        None => Slot::A,
+    }
+}
+
+/// Writes `data` at `offset` bytes into `slot`
+///
+/// Pages are erased as they are reached; the first write also erases the
+/// metadata of the slot, so a partial image never boots.
+pub fn write(
+    flash: Flash,
+    slot: Slot,
+    offset: u32,
+    data: &[u8],
+) -> Result<(), Error> {
+    if offset + u32(data.len()) > update::SLOT_SIZE {
+        return Err(Error::TooLarge);
+    }
+
+    if offset == 0 {
+        // NOTE the image must be linked for this slot: check its reset
+        // vector
+        if data.len() < 8 {
+            return Err(Error::WrongSlot);
+        }
+        let pc = u32(data[4]) | u32(data[5]) << 8 | u32(data[6]) << 16 |
+            u32(data[7]) << 24;
+        if !slot.contains(pc) {
+            return Err(Error::WrongSlot);
+        }
+
+        slot.erase(flash)?;
+    }
+
+    let address = slot.address() + offset;
+    for (i, pair) in data.chunks(2).enumerate() {
+        let address = address + 2 * u32(i);
+        if (address - flash::START) % flash::PAGE_SIZE == 0 {
+            flash.erase_page(address)?;
+        }
+
+        let low = u16(pair[0]);
+        let high = u16(*pair.get(1).unwrap_or(&0xFF));
+        flash.program(address, high << 8 | low)?;
+    }
+
+    Ok(())
+}
+
+/// Records the `len` bytes long image that's in `slot`
+///
+/// The image gets a higher version than the one in the other slot, so it's
+/// the next one `select` tries.
+pub fn install(
+    flash: Flash,
+    slot: Slot,
+    crc: &Crc,
+    rcc: &Rcc,
+    len: u32,
+) -> Result<(), Error> {
+    let version = match slot.other().image() {
+        Some(image) => image.version.wrapping_add(1),
+        None => 1,
+    };
+
+    let image = Image {
+        version: version,
+        len: len,
+        crc: crc32(crc, rcc, slot.address(), len),
+    };
+    slot.install(flash, &image)?;
+
+    Ok(())
+}
+
+/// Starts `app` as if it was coming out of reset
+pub fn boot(app: App) -> ! {
+    // NOTE(unsafe) the bootloader is done with everything
+    unsafe {
+        let rcc = &*RCC.get();
+        let nvic = &*NVIC.get();
+        let scb = &*SCB.get();
+
+        // Hand over the peripherals in their reset state
+        rcc.apb2rstr.write(
+            |w| w.usart1rst().bits(1).ioparst().bits(1).iopcrst().bits(1),
+        );
+        rcc.apb2rstr.write(|w| w.bits(0));
+        rcc.apb1rstr.write(|w| w.tim7rst().bits(1));
+        rcc.apb1rstr.write(|w| w.bits(0));
+        rcc.apb2enr.write(|w| w.bits(0));
+        rcc.apb1enr.write(|w| w.bits(0));
+        rcc.ahbenr.modify(|_, w| w.crcen().bits(0));
+        nvic.clear_pending(Interrupt::Usart1Irq);
+        nvic.clear_pending(Interrupt::Tim7Irq);
+
+        scb.vtor.write(app.vtor);
+
+        asm!("msr MSP, $0
+              bx $1"
+             :
+             : "r"(app.sp), "r"(app.pc)
+             :
+             : "volatile");
+    }
+
+    loop {}
+}
+
+/// Both slots, the one with the highest version first
+fn newest_first() -> [Slot; 2] {
+    let newest = match (Slot::A.image(), Slot::B.image()) {
+        (Some(a), Some(b)) if b.version > a.version => Slot::B,
+        (None, Some(_)) => Slot::B,
+        _ => Slot::A,
+    };
+
+    [newest, newest.other()]
+}
+
+/// CRC-32 of the `len` bytes at `address`, computed by the CRC unit
+///
+/// NOTE the last word is read whole: whatever follows the image, up to the
+/// next word boundary, is part of the sum
+fn crc32(crc: &Crc, rcc: &Rcc, address: u32, len: u32) -> u32 {
+    let crc = crc::Crc(crc);
+    crc.init(rcc);
+
+    // NOTE(unsafe) `address` and `len` are within a slot
+    let words = unsafe {
+        slice::from_raw_parts(address as *const u32, usize((len + 3) / 4))
+    };
+    crc.feed(words)
+}
+
+/// Reads the word at `address`
+fn word(address: u32) -> u32 {
+    unsafe { ptr::read_volatile(address as *const u32) }
+}
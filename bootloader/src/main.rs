@@ -0,0 +1,142 @@
+//! Serial bootloader for the STM32VLDISCOVERY
+//!
+//! Lives in the first 14 KiB of the flash and manages two application
+//! slots, see `valuelinediscovery::update`. At reset it starts the newest
+//! application that
+//!
+//! - is recorded in the metadata of its slot, with a matching CRC, and
+//! - is confirmed, or hasn't used up its boot attempts yet
+//!
+//! Before starting the application it starts the IWDG, with a
+//! `WATCHDOG_TIMEOUT` timeout: the application must keep feeding it. A new
+//! image that hangs, or resets before it confirms itself, gets another try
+//! until it runs out of attempts; then it's rejected and the image in the
+//! other slot runs again.
+//!
+//! If the user button is held, or there's nothing to start, it waits for a
+//! new image over USART1 (PA9/PA10, 115200 8N1), sent with YMODEM(-1K),
+//! e.g. `make send`; plain XMODEM can't tell the image from the padding of
+//! its last block, so it's refused. The image goes to the slot that doesn't
+//! hold the image to fall back to, and it must be linked for that slot. The
+//! image is written to the flash as it arrives; its metadata is only written
+//! once the whole image is in, so an interrupted update just means waiting
+//! for a new one at the next reset.
+//!
+//! The blue LED is on while the bootloader waits for an image; the green
+//! one too if the image goes to slot B.
+
+#![feature(asm)]
+#![feature(used)]
+#![no_std]
+
+extern crate cast;
+extern crate cortex_m;
+extern crate cortex_m_rt;
+extern crate stm32f100;
+extern crate valuelinediscovery as dsc;
+
+mod image;
+mod xmodem;
+
+use cortex_m::interrupt::{self, CriticalSection};
+use dsc::button::Button;
+use dsc::flash::Flash;
+use dsc::led::{self, LEDS};
+use dsc::serial::Serial;
+use dsc::timer::Timer;
+use dsc::update::Slot;
+use dsc::watchdog::IndependentWatchdog;
+use stm32f100::{CRC, FLASH, GPIOA, GPIOC, IWDG, RCC, TIM7, USART1};
+
+const BAUD_RATE: u32 = 115_200; // bits per second
+const WATCHDOG_TIMEOUT: u32 = 4_000; // ms
+
+fn main() {
+    if interrupt::free(update_requested) {
+        interrupt::free(update);
+    }
+
+    loop {
+        if let Some(app) = interrupt::free(select) {
+            image::boot(app)
+        }
+
+        interrupt::free(update);
+    }
+}
+
+/// Returns `true` if the user button is held
+fn update_requested(cs: &CriticalSection) -> bool {
+    let gpioa = GPIOA.borrow(cs);
+    let rcc = RCC.borrow(cs);
+
+    let button = Button(&gpioa);
+    button.init(&rcc);
+    button.is_pressed()
+}
+
+/// Picks the application to start and starts the IWDG for it
+fn select(cs: &CriticalSection) -> Option<image::App> {
+    let crc = CRC.borrow(cs);
+    let flash = FLASH.borrow(cs);
+    let iwdg = IWDG.borrow(cs);
+    let rcc = RCC.borrow(cs);
+
+    let flash = Flash(&flash);
+    flash.unlock();
+    let app = image::select(flash, &crc, &rcc);
+    flash.lock();
+
+    if app.is_some() {
+        IndependentWatchdog(&iwdg).start(WATCHDOG_TIMEOUT).ok();
+    }
+
+    app
+}
+
+/// Receives a new application and installs it in the free slot
+fn update(cs: &CriticalSection) {
+    let crc = CRC.borrow(cs);
+    let flash = FLASH.borrow(cs);
+    let gpioa = GPIOA.borrow(cs);
+    let gpioc = GPIOC.borrow(cs);
+    let rcc = RCC.borrow(cs);
+    let tim7 = TIM7.borrow(cs);
+    let usart1 = USART1.borrow(cs);
+
+    let slot = image::target(&crc, &rcc);
+
+    led::init(&gpioc, &rcc);
+    LEDS[0].on();
+    if slot == Slot::B {
+        LEDS[1].on();
+    }
+
+    let serial = Serial(&usart1);
+    serial.init(&gpioa, &rcc, BAUD_RATE);
+
+    // 1 ms ticks for the protocol timeouts
+    let timer = Timer(&tim7);
+    timer.init(&rcc, 1_000);
+    timer.resume();
+
+    let flash = Flash(&flash);
+    flash.unlock();
+    let result = xmodem::receive(serial, timer, |offset, data| {
+        image::write(flash, slot, offset, data)
+    });
+    if let Ok(len) = result {
+        image::install(flash, slot, &crc, &rcc, len).ok();
+    }
+    flash.lock();
+
+    LEDS[0].off();
+    LEDS[1].off();
+}
+
+// The bootloader doesn't use interrupts
+#[allow(dead_code)]
+#[used]
+#[link_section = ".rodata.interrupts"]
+static INTERRUPTS: stm32f100::interrupt::Handlers =
+    stm32f100::interrupt::DEFAULT_HANDLERS;
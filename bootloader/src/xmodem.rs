@@ -0,0 +1,264 @@
+//! YMODEM receiver
+//!
+//! Supports 128 byte (SOH) and 1 KiB (STX) blocks with CRC-16 checks. The
+//! YMODEM header (block 0) must come first, with the file size: it's what
+//! tells the file from the padding of its last block. Plain XMODEM has no
+//! header, so it's cancelled; batches of more than one file are not
+//! supported.
+
+use cast::{u16, u32};
+use dsc::serial::Serial;
+use dsc::timer::Timer;
+
+/// An error
+pub enum Error {
+    /// The sender cancelled the transfer
+    Cancelled,
+    /// The sender went silent
+    Timeout,
+    /// A block failed its checks
+    Corrupted,
+    /// Blocks arrived out of order
+    Sequence,
+    /// Too many bad blocks in a row
+    Retries,
+    /// The block couldn't be written
+    Write,
+    /// No YMODEM header, or one without the file size
+    Header,
+}
+
+// Control characters
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+// Asks for CRC-16 checks instead of checksums
+const CRC: u8 = b'C';
+
+// Timeouts, in ms
+const START_TIMEOUT: u32 = 3_000;
+const BYTE_TIMEOUT: u32 = 1_000;
+const BLOCK_TIMEOUT: u32 = 10_000;
+
+const MAX_RETRIES: u32 = 10;
+
+/// A received block
+enum Block {
+    /// Block number and length of the data in the buffer
+    Data(u8, usize),
+    /// End of transmission
+    End,
+}
+
+/// Receives a file, passing each block to `write` with its offset in the
+/// file, and returns the length of the file
+///
+/// Waits for a sender for as long as it takes.
+pub fn receive<F, E>(
+    serial: Serial,
+    timer: Timer,
+    mut write: F,
+) -> Result<u32, Error>
+where
+    F: FnMut(u32, &[u8]) -> Result<(), E>,
+{
+    let mut buffer = [0; 1024];
+    let mut offset = 0;
+    let mut expected = 1;
+    let mut retries = 0;
+
+    // Keep asking for a CRC transfer until the first block comes
+    let first = loop {
+        send(serial, CRC);
+        match block(serial, timer, &mut buffer, START_TIMEOUT) {
+            Ok(block) => break block,
+            // nobody's there yet, or line noise
+            Err(_) => {}
+        }
+    };
+
+    // YMODEM header: "name\0size ..."
+    let size = match first {
+        Block::Data(0, len) => parse_size(&buffer[..len]),
+        _ => None,
+    };
+    let size = match size {
+        Some(size) => size,
+        None => {
+            cancel(serial);
+            return Err(Error::Header);
+        }
+    };
+    send(serial, ACK);
+    send(serial, CRC);
+
+    let mut next = block(serial, timer, &mut buffer, BLOCK_TIMEOUT);
+    loop {
+        match next {
+            Ok(Block::Data(n, len)) if n == expected => {
+                // drop the padding of the last block
+                let left = size.saturating_sub(offset);
+                let len = if left < u32(len) { left as usize } else { len };
+
+                if write(offset, &buffer[..len]).is_err() {
+                    cancel(serial);
+                    return Err(Error::Write);
+                }
+
+                offset += u32(len);
+                expected = expected.wrapping_add(1);
+                retries = 0;
+                send(serial, ACK);
+            }
+            // The sender missed our ACK
+            Ok(Block::Data(n, _)) if n == expected.wrapping_sub(1) => {
+                send(serial, ACK);
+            }
+            Ok(Block::Data(..)) => {
+                cancel(serial);
+                return Err(Error::Sequence);
+            }
+            Ok(Block::End) => {
+                send(serial, ACK);
+                break;
+            }
+            Err(Error::Cancelled) => return Err(Error::Cancelled),
+            Err(_) => {
+                retries += 1;
+                if retries == MAX_RETRIES {
+                    cancel(serial);
+                    return Err(Error::Retries);
+                }
+                send(serial, NAK);
+            }
+        }
+
+        next = block(serial, timer, &mut buffer, BLOCK_TIMEOUT);
+    }
+
+    // YMODEM closes the batch with an empty header
+    send(serial, CRC);
+    if let Ok(Block::Data(0, _)) =
+        block(serial, timer, &mut buffer, BLOCK_TIMEOUT)
+    {
+        send(serial, ACK);
+    }
+
+    Ok(offset)
+}
+
+/// Receives one block into `buffer`
+fn block(
+    serial: Serial,
+    timer: Timer,
+    buffer: &mut [u8; 1024],
+    timeout: u32,
+) -> Result<Block, Error> {
+    let len = match read(serial, timer, timeout)? {
+        SOH => 128,
+        STX => 1024,
+        EOT => return Ok(Block::End),
+        CAN => return Err(Error::Cancelled),
+        _ => {
+            flush(serial, timer);
+            return Err(Error::Corrupted);
+        }
+    };
+
+    let n = read(serial, timer, BYTE_TIMEOUT)?;
+    let not_n = read(serial, timer, BYTE_TIMEOUT)?;
+    for byte in buffer[..len].iter_mut() {
+        *byte = read(serial, timer, BYTE_TIMEOUT)?;
+    }
+    let high = read(serial, timer, BYTE_TIMEOUT)?;
+    let low = read(serial, timer, BYTE_TIMEOUT)?;
+
+    if n != !not_n || crc16(&buffer[..len]) != u16(high) << 8 | u16(low) {
+        flush(serial, timer);
+        Err(Error::Corrupted)
+    } else {
+        Ok(Block::Data(n, len))
+    }
+}
+
+/// Reads a byte, waiting at most `timeout` ms
+fn read(serial: Serial, timer: Timer, mut timeout: u32) -> Result<u8, Error> {
+    loop {
+        if let Ok(byte) = serial.read() {
+            return Ok(byte);
+        }
+
+        if timer.clear_update_flag().is_ok() {
+            timeout -= 1;
+            if timeout == 0 {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+}
+
+/// Sends a byte
+fn send(serial: Serial, byte: u8) {
+    while serial.write(byte).is_err() {}
+}
+
+/// Drops whatever is left of a bad block
+fn flush(serial: Serial, timer: Timer) {
+    while read(serial, timer, BYTE_TIMEOUT).is_ok() {}
+}
+
+/// Aborts the transfer
+fn cancel(serial: Serial) {
+    for _ in 0..3 {
+        send(serial, CAN);
+    }
+}
+
+/// File size from a YMODEM header
+fn parse_size(header: &[u8]) -> Option<u32> {
+    let mut fields = header.split(|byte| *byte == 0);
+    fields.next();
+
+    let field = match fields.next() {
+        Some(field) => field,
+        None => return None,
+    };
+
+    // NOTE(checked_*) a size that doesn't fit is no size
+    let mut size = None;
+    for byte in field {
+        if *byte < b'0' || *byte > b'9' {
+            break;
+        }
+        size = match size.unwrap_or(0u32).checked_mul(10) {
+            Some(size) => size.checked_add(u32(*byte - b'0')),
+            None => None,
+        };
+        if size.is_none() {
+            return None;
+        }
+    }
+
+    size
+}
+
+/// CRC-16/XMODEM (poly 0x1021, init 0)
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+
+    for byte in data {
+        crc ^= u16(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+
+    crc
+}
@@ -0,0 +1,653 @@
+//! Decodes the records of `binlog!` and prints them formatted
+//!
+//! Runs on the host; the wire format is the one in
+//! `valuelinediscovery/src/binlog/wire.rs`.
+//!
+//! - `stm32-binlog ELF [FILE]` reads the format strings from the symbols of
+//!   the `.binlog` section of ELF, the program running on the device, then
+//!   decodes the records in FILE, or the standard input, as they come. FILE
+//!   is the output of the serial port, or of ITM stimulus port 31:
+//!   `stm32-itm -p 31 CAPTURE | stm32-binlog ELF`.
+//!
+//! A first record that doesn't decode is skipped, so decoding can start in
+//! the middle of a record.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process;
+
+#[path = "../../../valuelinediscovery/src/binlog/wire.rs"]
+#[allow(dead_code)]
+mod wire;
+
+// Symbol type of data objects, like the `static`s of `binlog!`
+const STT_OBJECT: u8 = 1;
+
+const LEVELS: [&str; 6] = ["?", "ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+
+    if args.is_empty() || args.len() > 2 {
+        eprintln!("usage: stm32-binlog ELF [FILE]");
+        process::exit(1);
+    }
+
+    let strings = match read(&args[0]).and_then(|elf| strings(&elf)) {
+        Ok(strings) => strings,
+        Err(e) => {
+            eprintln!("{}: {}", args[0], e);
+            process::exit(1);
+        }
+    };
+
+    let input: Box<dyn Read> = match args.get(1) {
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+
+    let stdout = io::stdout();
+    let stderr = io::stderr();
+    let result = decode(
+        &strings,
+        BufReader::new(input),
+        &mut stdout.lock(),
+        &mut stderr.lock(),
+    );
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Decodes the records of `input`, writing them to `out` and the bad ones
+/// to `err`
+///
+/// A first record that doesn't decode is skipped silently: it's the tail of
+/// a record sent before the capture started.
+fn decode<R, W, E>(
+    strings: &HashMap<u32, String>,
+    mut input: R,
+    out: &mut W,
+    err: &mut E,
+) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+    E: Write,
+{
+    let mut first = true;
+    let mut frame = vec![];
+    loop {
+        frame.clear();
+        if input.read_until(0, &mut frame)? == 0 {
+            break;
+        }
+
+        if frame.last() != Some(&0) {
+            // end of the input in the middle of a record
+            break;
+        }
+        frame.pop();
+
+        match cobs(&frame).and_then(|record| format(strings, &record)) {
+            Ok(line) => writeln!(out, "{}", line)?,
+            Err(_) if first => {}
+            Err(e) => {
+                let hex: Vec<_> =
+                    frame.iter().map(|b| format!("{:02X}", b)).collect();
+                writeln!(err, "bad record [{}]: {}", hex.join(" "), e)?
+            }
+        }
+        first = false;
+    }
+
+    Ok(())
+}
+
+/// The format strings, by index
+fn strings(elf: &[u8]) -> Result<HashMap<u32, String>, String> {
+    if elf.len() < 52 || &elf[..4] != b"\x7fELF" {
+        return Err("not an ELF file".to_string());
+    }
+    if elf[4] != 1 || elf[5] != 1 {
+        return Err("not a 32-bit little endian ELF file".to_string());
+    }
+
+    let section = |i: usize| -> Result<Section, String> {
+        let shoff = u32le(elf, 0x20)? as usize;
+        let shentsize = u16le(elf, 0x2E)? as usize;
+        let offset = shoff + i * shentsize;
+        Ok(Section {
+            name: u32le(elf, offset)?,
+            kind: u32le(elf, offset + 4)?,
+            offset: u32le(elf, offset + 16)? as usize,
+            size: u32le(elf, offset + 20)? as usize,
+            link: u32le(elf, offset + 24)? as usize,
+        })
+    };
+    let shnum = u16le(elf, 0x30)? as usize;
+    let names = section(u16le(elf, 0x32)? as usize)?;
+
+    let mut binlog = None;
+    let mut symtab = None;
+    for i in 0..shnum {
+        let s = section(i)?;
+        if name(elf, &names, s.name)? == ".binlog" {
+            binlog = Some(i);
+        }
+        // SHT_SYMTAB
+        if s.kind == 2 {
+            symtab = Some(s);
+        }
+    }
+
+    let binlog = binlog.ok_or("no .binlog section; no `binlog!` used?")?;
+    let symtab = symtab.ok_or("no symbol table; stripped?")?;
+    let symbol_names = section(symtab.link)?;
+
+    let mut strings = HashMap::new();
+    for offset in (symtab.offset..symtab.offset + symtab.size).step_by(16) {
+        // NOTE(STT_OBJECT) skips the section symbol and mapping symbols
+        // like `$d`, which have the address of a string too
+        let kind = elf.get(offset + 12).ok_or("truncated ELF file")? & 0xF;
+        if kind == STT_OBJECT && u16le(elf, offset + 14)? as usize == binlog
+        {
+            let value = u32le(elf, offset + 4)?;
+            let string = name(elf, &symbol_names, u32le(elf, offset)?)?;
+            strings.insert(value, string);
+        }
+    }
+
+    Ok(strings)
+}
+
+struct Section {
+    name: u32,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+/// The string at `offset` in the string table `table`
+fn name(elf: &[u8], table: &Section, offset: u32) -> Result<String, String> {
+    let start = table.offset + offset as usize;
+    let bytes = elf.get(start..table.offset + table.size).ok_or("truncated")?;
+    let end = bytes.iter().position(|b| *b == 0).ok_or("unterminated")?;
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// Decodes a COBS encoded record, without its zero byte
+fn cobs(encoded: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < encoded.len() {
+        let code = encoded[i] as usize;
+        let block = encoded
+            .get(i + 1..i + code)
+            .ok_or("COBS block past the end")?;
+        decoded.extend_from_slice(block);
+        i += code;
+        if code < 0xFF && i < encoded.len() {
+            decoded.push(0);
+        }
+    }
+    Ok(decoded)
+}
+
+/// A decoded argument
+enum Arg {
+    Unsigned(u64),
+    /// Value and size, in bytes, on the device
+    Signed(i64, usize),
+    Bool(bool),
+    Float(f32),
+    Str(String),
+}
+
+/// Formats a decoded record
+fn format(
+    strings: &HashMap<u32, String>,
+    record: &[u8],
+) -> Result<String, String> {
+    if record.len() < 3 {
+        return Err("too short".to_string());
+    }
+
+    let header = record[0];
+    let level = LEVELS
+        .get((header & wire::LEVEL_MASK) as usize)
+        .unwrap_or(&LEVELS[0]);
+    let index = u32::from(record[1]) | u32::from(record[2]) << 8;
+    let string = strings
+        .get(&index)
+        .ok_or_else(|| format!("unknown format string {}", index))?;
+
+    let mut args = vec![];
+    let mut rest = &record[3..];
+    while let Some((&tag, tail)) = rest.split_first() {
+        let (value, tail) = match wire::size(tag) {
+            Some(size) if size <= tail.len() => tail.split_at(size),
+            None if tag == wire::STR && !tail.is_empty() => {
+                let len = tail[0] as usize;
+                if len + 1 > tail.len() {
+                    return Err("string past the end".to_string());
+                }
+                tail[1..].split_at(len)
+            }
+            _ => return Err(format!("bad argument tag {}", tag)),
+        };
+        args.push(arg(tag, value));
+        rest = tail;
+    }
+
+    let mut line = format!("{:5} {}", level, interpolate(string, &args));
+    if header & wire::TRUNCATED != 0 {
+        line.push_str(" (truncated)");
+    }
+    Ok(line)
+}
+
+/// Decodes the argument `value` tagged `tag`
+fn arg(tag: u8, value: &[u8]) -> Arg {
+    let unsigned = value
+        .iter()
+        .rev()
+        .fold(0u64, |acc, byte| acc << 8 | u64::from(*byte));
+
+    match tag {
+        wire::I8 => Arg::Signed(i64::from(unsigned as i8), 1),
+        wire::I16 => Arg::Signed(i64::from(unsigned as i16), 2),
+        wire::I32 => Arg::Signed(i64::from(unsigned as i32), 4),
+        wire::I64 => Arg::Signed(unsigned as i64, 8),
+        wire::BOOL => Arg::Bool(unsigned != 0),
+        wire::F32 => Arg::Float(f32::from_bits(unsigned as u32)),
+        wire::STR => Arg::Str(String::from_utf8_lossy(value).into_owned()),
+        _ => Arg::Unsigned(unsigned),
+    }
+}
+
+/// Replaces the `{}`s of `string` with `args`
+///
+/// `{:x}`, `{:X}`, `{:#x}` and `{:#X}` print integers in hex; `{{` and `}}`
+/// are braces. Missing arguments print as `{?}`.
+fn interpolate(string: &str, args: &[Arg]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = string.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let spec: String =
+                    chars.by_ref().take_while(|c| *c != '}').collect();
+                match args.next() {
+                    Some(arg) => out.push_str(&display(arg, &spec)),
+                    None => out.push_str("{?}"),
+                }
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Formats `arg` as the format spec `spec` says
+fn display(arg: &Arg, spec: &str) -> String {
+    match (arg, spec) {
+        (Arg::Unsigned(n), ":x") => format!("{:x}", n),
+        (Arg::Unsigned(n), ":X") => format!("{:X}", n),
+        (Arg::Unsigned(n), ":#x") => format!("{:#x}", n),
+        (Arg::Unsigned(n), ":#X") => format!("{:#X}", n),
+        // NOTE like Rust, hex shows the two's complement at the argument's
+        // own width: `-1i8` is `ff`
+        (Arg::Signed(n, size), ":x" | ":X" | ":#x" | ":#X") => {
+            let bits = *n as u64 & u64::MAX >> (64 - 8 * size);
+            display(&Arg::Unsigned(bits), spec)
+        }
+        (Arg::Unsigned(n), _) => n.to_string(),
+        (Arg::Signed(n, _), _) => n.to_string(),
+        (Arg::Bool(b), _) => b.to_string(),
+        (Arg::Float(f), _) => f.to_string(),
+        (Arg::Str(s), _) => s.clone(),
+    }
+}
+
+fn u16le(bytes: &[u8], offset: usize) -> Result<u16, String> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from(b[0]) | u16::from(b[1]) << 8)
+        .ok_or_else(|| "truncated ELF file".to_string())
+}
+
+fn u32le(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| {
+            u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 |
+                u32::from(b[3]) << 24
+        })
+        .ok_or_else(|| "truncated ELF file".to_string())
+}
+
+/// Reads the whole file at `path`
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{cobs, decode, format, interpolate, strings, Arg};
+    use wire;
+
+    /// COBS encodes `record` and appends the zero byte, like the device
+    fn encode(record: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![];
+        for block in record.split(|byte| *byte == 0) {
+            encoded.push(block.len() as u8 + 1);
+            encoded.extend_from_slice(block);
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn table() -> HashMap<u32, String> {
+        let mut strings = HashMap::new();
+        strings.insert(0, "started".to_string());
+        strings.insert(1, "x = {}".to_string());
+        strings.insert(2, "{:x} {:X} {:#x} {:#X}".to_string());
+        strings
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        let payloads: &[&[u8]] = &[
+            &[],
+            &[0],
+            &[0, 0],
+            &[1, 2, 3],
+            &[0, 1, 0, 2, 0],
+            &[0x11, 0x22, 0x00, 0x33],
+            &[0xFF; 63],
+        ];
+
+        for payload in payloads {
+            let encoded = encode(payload);
+            assert_eq!(encoded.last(), Some(&0));
+            assert!(!encoded[..encoded.len() - 1].contains(&0));
+            assert_eq!(&cobs(&encoded[..encoded.len() - 1]).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn cobs_rejects_block_past_the_end() {
+        assert!(cobs(&[5, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn every_tag() {
+        let mut strings = HashMap::new();
+        strings.insert(7, "{} {} {} {} {} {} {} {} {} {} {}".to_string());
+
+        let record = [
+            3, 7, 0,
+            wire::U8, 0xFF,
+            wire::U16, 0x34, 0x12,
+            wire::U32, 0x78, 0x56, 0x34, 0x12,
+            wire::U64, 1, 0, 0, 0, 0, 0, 0, 0x80,
+            wire::I8, 0xFE,
+            wire::I16, 0x00, 0x80,
+            wire::I32, 0xFF, 0xFF, 0xFF, 0xFF,
+            wire::I64, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            wire::BOOL, 1,
+            wire::F32, 0x00, 0x00, 0xC0, 0x3F,
+            wire::STR, 2, b'h', b'i',
+        ];
+
+        assert_eq!(
+            format(&strings, &record).unwrap(),
+            "INFO  255 4660 305419896 9223372036854775809 -2 -32768 -1 -2 \
+             true 1.5 hi"
+        );
+    }
+
+    #[test]
+    fn levels() {
+        let strings = table();
+        assert_eq!(format(&strings, &[1, 0, 0]).unwrap(), "ERROR started");
+        assert_eq!(format(&strings, &[5, 0, 0]).unwrap(), "TRACE started");
+        assert_eq!(format(&strings, &[0, 0, 0]).unwrap(), "?     started");
+    }
+
+    #[test]
+    fn truncated() {
+        let strings = table();
+        let record = [2 | wire::TRUNCATED, 1, 0, wire::U8, 42];
+
+        assert_eq!(
+            format(&strings, &record).unwrap(),
+            "WARN  x = 42 (truncated)"
+        );
+    }
+
+    #[test]
+    fn bad_records() {
+        let strings = table();
+
+        assert!(format(&strings, &[3, 0]).is_err());
+        assert!(format(&strings, &[3, 9, 0]).is_err());
+        assert!(format(&strings, &[3, 1, 0, 0xEE, 1]).is_err());
+        assert!(format(&strings, &[3, 1, 0, wire::U32, 1, 2]).is_err());
+        assert!(format(&strings, &[3, 1, 0, wire::STR, 5, b'a']).is_err());
+    }
+
+    #[test]
+    fn hex() {
+        let strings = table();
+        let record = [
+            3, 2, 0,
+            wire::U8, 0xAB,
+            wire::U16, 0xCD, 0xAB,
+            wire::U32, 0xEF, 0xBE, 0xAD, 0xDE,
+            wire::I8, 0xFF,
+        ];
+
+        assert_eq!(
+            format(&strings, &record).unwrap(),
+            "INFO  ab ABCD 0xdeadbeef 0xFF"
+        );
+    }
+
+    #[test]
+    fn signed_hex_keeps_the_width() {
+        let mut strings = HashMap::new();
+        strings.insert(4, "{:x} {:#X} {:#x} {}".to_string());
+        let record = [
+            3, 4, 0,
+            wire::I16, 0xFE, 0xFF,
+            wire::I32, 0xFF, 0xFF, 0xFF, 0xFF,
+            wire::I64, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            wire::I8, 0xFF,
+        ];
+
+        assert_eq!(
+            format(&strings, &record).unwrap(),
+            "INFO  fffe 0xFFFFFFFF 0xfffffffffffffffe -1"
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        let args = [Arg::Unsigned(1)];
+
+        assert_eq!(interpolate("{{{}}}", &args), "{1}");
+        assert_eq!(interpolate("{} {}", &args), "1 {?}");
+        assert_eq!(interpolate("no args", &args), "no args");
+    }
+
+    fn run(input: &[u8]) -> (String, String) {
+        let (mut out, mut err) = (vec![], vec![]);
+        decode(&table(), input, &mut out, &mut err).unwrap();
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+        )
+    }
+
+    #[test]
+    fn first_record_is_kept() {
+        let mut input = encode(&[3, 0, 0]);
+        input.extend(encode(&[3, 1, 0, wire::U8, 7]));
+
+        let (out, err) = run(&input);
+        assert_eq!(out, "INFO  started\nINFO  x = 7\n");
+        assert_eq!(err, "");
+    }
+
+    #[test]
+    fn partial_first_record_is_skipped() {
+        let mut input = encode(&[3, 1, 0, wire::U32, 1, 2, 3, 4]);
+        input.drain(..5);
+        input.extend(encode(&[3, 1, 0, wire::U8, 7]));
+        // the end of the capture cuts a record
+        input.extend(&encode(&[3, 0, 0])[..2]);
+
+        let (out, err) = run(&input);
+        assert_eq!(out, "INFO  x = 7\n");
+        assert_eq!(err, "");
+    }
+
+    #[test]
+    fn later_bad_records_are_reported() {
+        let mut input = encode(&[3, 0, 0]);
+        input.extend(encode(&[3, 9, 0]));
+
+        let (out, err) = run(&input);
+        assert_eq!(out, "INFO  started\n");
+        assert_eq!(err, "bad record [03 03 09 01]: unknown format string 9\n");
+    }
+
+    /// A minimal ELF file with a `.binlog` section
+    fn elf(symbols: &[(&str, u32, u8)]) -> Vec<u8> {
+        fn u16le(bytes: &mut Vec<u8>, value: u16) {
+            bytes.extend(&[value as u8, (value >> 8) as u8]);
+        }
+        fn u32le(bytes: &mut Vec<u8>, value: u32) {
+            u16le(bytes, value as u16);
+            u16le(bytes, (value >> 16) as u16);
+        }
+
+        // section names
+        let shstrtab = b"\0.binlog\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+
+        // symbol names and symbols; the first symbol is the null one
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for &(name, value, info) in symbols {
+            u32le(&mut symtab, strtab.len() as u32);
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+            u32le(&mut symtab, value);
+            u32le(&mut symtab, 1);
+            symtab.push(info);
+            symtab.push(0);
+            u16le(&mut symtab, 1);
+        }
+
+        let symtab_offset = 52;
+        let strtab_offset = symtab_offset + symtab.len();
+        let shstrtab_offset = strtab_offset + strtab.len();
+        let shoff = shstrtab_offset + shstrtab.len();
+
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(16, 0);
+        u16le(&mut elf, 2); // ET_EXEC
+        u16le(&mut elf, 40); // EM_ARM
+        u32le(&mut elf, 1);
+        u32le(&mut elf, 0);
+        u32le(&mut elf, 0);
+        u32le(&mut elf, shoff as u32);
+        u32le(&mut elf, 0);
+        u16le(&mut elf, 52);
+        u16le(&mut elf, 0);
+        u16le(&mut elf, 0);
+        u16le(&mut elf, 40);
+        u16le(&mut elf, 5);
+        u16le(&mut elf, 4);
+
+        elf.extend(&symtab);
+        elf.extend(&strtab);
+        elf.extend(&shstrtab);
+
+        // name, type, offset, size, link
+        let sections = [
+            (0, 0, 0, 0, 0),
+            (1, 8, 0, 16, 0),
+            (9, 2, symtab_offset, symtab.len(), 3),
+            (17, 3, strtab_offset, strtab.len(), 0),
+            (25, 3, shstrtab_offset, shstrtab.len(), 0),
+        ];
+        for &(name, kind, offset, size, link) in &sections {
+            u32le(&mut elf, name);
+            u32le(&mut elf, kind);
+            u32le(&mut elf, 0);
+            u32le(&mut elf, 0);
+            u32le(&mut elf, offset as u32);
+            u32le(&mut elf, size as u32);
+            u32le(&mut elf, link);
+            u32le(&mut elf, 0);
+            u32le(&mut elf, 1);
+            u32le(&mut elf, 0);
+        }
+
+        elf
+    }
+
+    #[test]
+    fn elf_strings() {
+        let elf = elf(&[
+            // STT_SECTION
+            ("", 0, 0x03),
+            // global STT_OBJECTs
+            ("x = {}", 0, 0x11),
+            ("y = {:#x}", 1, 0x11),
+            // local STT_NOTYPE mapping symbol
+            ("$d", 0, 0x00),
+        ]);
+
+        let strings = strings(&elf).unwrap();
+        assert_eq!(strings.len(), 2);
+        assert_eq!(strings[&0], "x = {}");
+        assert_eq!(strings[&1], "y = {:#x}");
+    }
+
+    #[test]
+    fn elf_errors() {
+        assert!(strings(&[0x7F; 64]).is_err());
+        assert!(strings(&elf(&[])[..60]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! CRCs of files, as computed by the CRC unit of the STM32 and as the
+//! standard CRC-32, for checking what the device computes
+//!
+//! Runs on the host; the algorithm is the one in
+//! `valuelinediscovery/src/crc/software.rs`.
+//!
+//! - `stm32-crc FILE..` prints the CRC unit result for each file, read as
+//!   little endian words, and its standard CRC-32. The last word is padded
+//!   with 0xFF, like erased flash, so the first column matches the CRC the
+//!   bootloader records for an image.
+//! - `stm32-crc --vectors` prints a few test vectors.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::process;
+
+#[path = "../../../valuelinediscovery/src/crc/software.rs"]
+#[allow(dead_code)]
+mod software;
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+
+    if args.is_empty() {
+        eprintln!("usage: stm32-crc FILE.. | stm32-crc --vectors");
+        process::exit(1);
+    }
+
+    if args[0] == "--vectors" {
+        vectors();
+        return;
+    }
+
+    for path in &args {
+        match read(path) {
+            Ok(bytes) => {
+                println!(
+                    "{:08X}  {:08X}  {}",
+                    software::checksum(&words(&bytes)),
+                    software::standard(&bytes),
+                    path
+                )
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Prints the CRC unit result for some words, and both CRCs for some bytes
+fn vectors() {
+    let vectors: &[&[u32]] = &[
+        &[0x0000_0000],
+        &[0xFFFF_FFFF],
+        &[0x1234_5678],
+        &[0x1234_5678, 0x9ABC_DEF0],
+    ];
+    // NOTE whole words only: the CRC unit can't do the rest
+    let strings: &[&[u8]] = &[b"", b"1234", b"12345678", b"123456789abc"];
+
+    println!("CRC unit, words");
+    for words in vectors {
+        let hex: Vec<_> = words.iter().map(|w| format!("{:08X}", w)).collect();
+        println!("{:08X}  [{}]", software::checksum(words), hex.join(", "));
+    }
+
+    println!();
+    println!("CRC unit, bytes fed through `to_unit`; standard CRC-32");
+    for bytes in strings {
+        let unit = words(bytes).iter().fold(software::INIT, |crc, word| {
+            software::update(crc, software::to_unit(*word))
+        });
+        println!(
+            "{:08X}  {:08X}  {:?}",
+            unit,
+            software::standard(bytes),
+            String::from_utf8_lossy(bytes)
+        );
+    }
+}
+
+/// `bytes` as little endian words, the last one padded with 0xFF
+fn words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0xFF; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from(word[0]) | u32::from(word[1]) << 8 |
+                u32::from(word[2]) << 16 | u32::from(word[3]) << 24
+        })
+        .collect()
+}
+
+/// Reads the whole file at `path`
+fn read(path: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
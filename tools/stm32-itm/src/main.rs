@@ -0,0 +1,632 @@
+//! Decodes the ITM/DWT packets of a SWO capture, in place of `itmdump`
+//!
+//! Runs on the host. The input is what OpenOCD writes with
+//! `monitor tpiu config internal FILE uart off 8000000 2000000`: the raw
+//! packet stream, formatter bypassed. FILE can be a regular file or a FIFO
+//! (`mkfifo`); either way it's decoded as it comes.
+//!
+//! - `stm32-itm [FILE]` prints every packet, one per line, from FILE or the
+//!   standard input. The text of a stimulus port is printed a line at a
+//!   time.
+//! - `stm32-itm -p PORT [FILE]` writes only the bytes sent to stimulus port
+//!   PORT, as they are, e.g. to pipe port 31 into `stm32-binlog`.
+//! - `stm32-itm -d DIR [FILE]` writes the bytes of each stimulus port N to
+//!   `DIR/port-N`, and prints the other packets.
+//!
+//! Local timestamps, once they show up, prefix the lines with the time in
+//! timestamp clock cycles: the packets before a timestamp packet are the
+//! ones it times. Global timestamps are printed as they are.
+//!
+//! The DWT packets are only sent if enabled, e.g. from gdb, for timestamps,
+//! exception trace and PC sampling every 1024 cycles:
+//!
+//! ``` text
+//! monitor mww 0xE0000E80 0x0001000F  # ITM_TCR: TSENA, SYNCENA, DWTENA
+//! monitor mww 0xE0001000 0x0001123F  # DWT_CTRL: EXCTRCENA, PCSAMPLENA
+//! ```
+//!
+//! `captures/sample.itm` has every kind of packet, put together by hand
+//! from the packet formats; its output is `captures/sample.txt`. `cargo
+//! test` checks the decoder against it, no hardware needed.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process;
+
+/// A decoded packet
+enum Packet {
+    /// Synchronization
+    Sync,
+    /// Packets were lost
+    Overflow,
+    /// Bytes written to a stimulus port
+    Instrumentation { port: u8, payload: Vec<u8> },
+    /// DWT counters that wrapped around
+    EventCounter(u8),
+    /// An exception was entered, exited or returned to
+    Exception { number: u16, function: u8 },
+    /// A PC sample; `None` if the core was sleeping
+    PcSample(Option<u32>),
+    /// PC of a data access that hit a DWT comparator
+    DataPc { comparator: u8, pc: u32 },
+    /// Address offset of a data access that hit a DWT comparator
+    DataAddress { comparator: u8, offset: u16 },
+    /// Value of a data access that hit a DWT comparator
+    DataValue { comparator: u8, write: bool, value: u32 },
+    /// Time since the previous local timestamp
+    LocalTimestamp(u32),
+    /// Low bits of the global timestamp
+    GlobalTimestamp1(u32),
+    /// High bits of the global timestamp
+    GlobalTimestamp2(u32),
+    /// Extension, e.g. the page of the stimulus ports
+    Extension { source: u8, info: u32 },
+    /// Something this decoder doesn't know
+    Unknown(Vec<u8>),
+}
+
+/// How many bytes follow a header
+enum Payload {
+    /// This many
+    Fixed(usize),
+    /// Until one with the top bit cleared, at most this many
+    Continued(usize),
+}
+
+/// Turns the byte stream into packets
+#[derive(Default)]
+struct Decoder {
+    // consecutive zero bytes, for synchronization
+    zeros: usize,
+    // packet being received: header and payload
+    packet: Vec<u8>,
+    // bytes the packet still needs, if known
+    needed: usize,
+    continued: bool,
+}
+
+impl Decoder {
+    /// Takes the next byte, and returns the packet it completes, if any
+    fn feed(&mut self, byte: u8) -> Option<Packet> {
+        if self.packet.is_empty() {
+            return self.header(byte);
+        }
+
+        self.packet.push(byte);
+        if self.continued {
+            self.needed -= 1;
+            if byte & 0x80 != 0 && self.needed > 0 {
+                return None;
+            }
+        } else {
+            self.needed -= 1;
+            if self.needed > 0 {
+                return None;
+            }
+        }
+
+        let packet = decode(&self.packet);
+        self.packet.clear();
+        Some(packet)
+    }
+
+    fn header(&mut self, byte: u8) -> Option<Packet> {
+        if byte == 0 {
+            self.zeros += 1;
+            return None;
+        }
+
+        let zeros = self.zeros;
+        self.zeros = 0;
+        if byte == 0x80 && zeros >= 5 {
+            return Some(Packet::Sync);
+        }
+
+        match payload(byte) {
+            None => Some(decode(&[byte])),
+            Some(Payload::Fixed(n)) => {
+                self.packet.push(byte);
+                self.needed = n;
+                self.continued = false;
+                None
+            }
+            Some(Payload::Continued(n)) => {
+                self.packet.push(byte);
+                self.needed = n;
+                self.continued = true;
+                None
+            }
+        }
+    }
+}
+
+/// Payload that follows `header`; `None` if there's none
+fn payload(header: u8) -> Option<Payload> {
+    match header & 0b11 {
+        0b01 => Some(Payload::Fixed(1)),
+        0b10 => Some(Payload::Fixed(2)),
+        0b11 => Some(Payload::Fixed(4)),
+        // protocol packets
+        _ => {
+            if header & 0x0F == 0 {
+                // local timestamp: format 1 has a payload, format 2 doesn't
+                if header & 0x80 != 0 {
+                    Some(Payload::Continued(4))
+                } else {
+                    None
+                }
+            } else if header == 0x94 {
+                Some(Payload::Continued(4))
+            } else if header == 0xB4 {
+                Some(Payload::Continued(6))
+            } else if header & 0x0B == 0x08 && header & 0x80 != 0 {
+                Some(Payload::Continued(4))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Decodes a whole packet, header first
+fn decode(packet: &[u8]) -> Packet {
+    let header = packet[0];
+    let payload = &packet[1..];
+
+    if header & 0b11 != 0 {
+        let id = header >> 3;
+        let value = little_endian(payload);
+
+        if header & 0b100 == 0 {
+            return Packet::Instrumentation {
+                port: id,
+                payload: payload.to_vec(),
+            };
+        }
+
+        return match (id, payload.len()) {
+            (0, 1) => Packet::EventCounter(payload[0]),
+            (1, 2) => Packet::Exception {
+                number: (value & 0x1FF) as u16,
+                function: ((value >> 12) & 0b11) as u8,
+            },
+            (2, 4) => Packet::PcSample(Some(value)),
+            (2, 1) if value == 0 => Packet::PcSample(None),
+            (8..=15, 4) if id & 1 == 0 => Packet::DataPc {
+                comparator: (id >> 1) & 0b11,
+                pc: value,
+            },
+            (8..=15, 2) if id & 1 == 1 => Packet::DataAddress {
+                comparator: (id >> 1) & 0b11,
+                offset: value as u16,
+            },
+            (16..=23, _) => Packet::DataValue {
+                comparator: (id >> 1) & 0b11,
+                write: id & 1 == 1,
+                value,
+            },
+            _ => Packet::Unknown(packet.to_vec()),
+        };
+    }
+
+    match header {
+        0x70 => Packet::Overflow,
+        0x10..=0x60 if header & 0x0F == 0 => {
+            Packet::LocalTimestamp(u32::from(header >> 4))
+        }
+        0xC0 | 0xD0 | 0xE0 | 0xF0 => {
+            Packet::LocalTimestamp(continued(payload))
+        }
+        0x94 => Packet::GlobalTimestamp1(continued(payload)),
+        0xB4 => Packet::GlobalTimestamp2(continued(payload)),
+        // NOTE(0x0B) bit 2 is the source, SH
+        _ if header & 0x0B == 0x08 => Packet::Extension {
+            source: (header >> 2) & 1,
+            info: u32::from((header >> 4) & 0b111) |
+                continued(payload) << 3,
+        },
+        _ => Packet::Unknown(packet.to_vec()),
+    }
+}
+
+/// Value of little endian `bytes`
+fn little_endian(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |acc, byte| acc << 8 | u32::from(*byte))
+}
+
+/// Value of a payload of 7 bits per byte, the top bit a continuation flag
+fn continued(bytes: &[u8]) -> u32 {
+    bytes.iter().enumerate().fold(0, |acc, (i, byte)| {
+        acc | u32::from(byte & 0x7F).checked_shl(7 * i as u32).unwrap_or(0)
+    })
+}
+
+/// Name of exception `number`
+fn exception(number: u16) -> String {
+    match number {
+        0 => "Thread".to_string(),
+        1 => "Reset".to_string(),
+        2 => "NMI".to_string(),
+        3 => "HardFault".to_string(),
+        4 => "MemManage".to_string(),
+        5 => "BusFault".to_string(),
+        6 => "UsageFault".to_string(),
+        11 => "SVCall".to_string(),
+        12 => "DebugMonitor".to_string(),
+        14 => "PendSV".to_string(),
+        15 => "SysTick".to_string(),
+        n if n >= 16 => format!("IRQ {}", n - 16),
+        n => format!("exception {}", n),
+    }
+}
+
+/// Describes `packet`; `None` for packets with nothing to say
+fn describe(packet: &Packet) -> Option<String> {
+    Some(match *packet {
+        Packet::Sync | Packet::Instrumentation { .. } => return None,
+        Packet::LocalTimestamp(_) => return None,
+        Packet::Overflow => "overflow: packets were lost".to_string(),
+        Packet::EventCounter(counters) => {
+            let names = ["CPI", "EXC", "SLEEP", "LSU", "FOLD", "CYC"];
+            let wrapped: Vec<_> = names
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| counters & (1 << i) != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            format!("counters wrapped: {}", wrapped.join(" "))
+        }
+        Packet::Exception { number, function } => {
+            let function = match function {
+                1 => "entered",
+                2 => "exited",
+                3 => "returned to",
+                _ => "?",
+            };
+            format!("exception {} {}", exception(number), function)
+        }
+        Packet::PcSample(Some(pc)) => format!("PC {:#010x}", pc),
+        Packet::PcSample(None) => "PC sleeping".to_string(),
+        Packet::DataPc { comparator, pc } => {
+            format!("comparator {}: access from PC {:#010x}", comparator, pc)
+        }
+        Packet::DataAddress { comparator, offset } => {
+            format!("comparator {}: address offset {:#06x}", comparator, offset)
+        }
+        Packet::DataValue {
+            comparator,
+            write,
+            value,
+        } => {
+            format!(
+                "comparator {}: {} {:#x}",
+                comparator,
+                if write { "wrote" } else { "read" },
+                value
+            )
+        }
+        Packet::GlobalTimestamp1(bits) => format!("global time, low {}", bits),
+        Packet::GlobalTimestamp2(bits) => {
+            format!("global time, high {}", bits)
+        }
+        Packet::Extension { source, info } => {
+            format!("extension {}: {:#x}", source, info)
+        }
+        Packet::Unknown(ref bytes) => {
+            let hex: Vec<_> =
+                bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            format!("unknown packet [{}]", hex.join(" "))
+        }
+    })
+}
+
+/// Prints lines, with the local timestamp that follows them
+#[derive(Default)]
+struct Printer {
+    // time of the last local timestamp; `None` until one shows up
+    time: Option<u64>,
+    // lines waiting for their timestamp
+    pending: Vec<String>,
+    // text of each stimulus port, up to its next newline
+    text: HashMap<u8, Vec<u8>>,
+}
+
+impl Printer {
+    fn packet<W>(
+        &mut self,
+        out: &mut W,
+        packet: &Packet,
+        ports: bool,
+    ) -> io::Result<()>
+    where
+        W: Write,
+    {
+        match *packet {
+            Packet::LocalTimestamp(delta) => {
+                let time = self.time.unwrap_or(0) + u64::from(delta);
+                self.time = Some(time);
+                for line in self.pending.drain(..) {
+                    writeln!(out, "[{:>10}] {}", time, line)?;
+                }
+            }
+            Packet::Instrumentation { port, ref payload } if ports => {
+                let text = self.text.entry(port).or_default();
+                let mut lines = vec![];
+                for byte in payload {
+                    match *byte {
+                        b'\n' => {
+                            let line = show(text);
+                            lines.push(format!("port {}: {}", port, line));
+                            text.clear();
+                        }
+                        b'\r' => {}
+                        byte => text.push(byte),
+                    }
+                }
+                for line in lines {
+                    self.line(out, line)?;
+                }
+            }
+            _ => {
+                if let Some(line) = describe(packet) {
+                    self.line(out, line)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn line<W>(&mut self, out: &mut W, line: String) -> io::Result<()>
+    where
+        W: Write,
+    {
+        if self.time.is_some() {
+            self.pending.push(line);
+            Ok(())
+        } else {
+            writeln!(out, "{}", line)
+        }
+    }
+
+    /// Prints what's left at the end of the input
+    fn flush<W>(&mut self, out: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for line in self.pending.drain(..) {
+            writeln!(out, "[{:>10}] {}", "?", line)?;
+        }
+
+        let mut ports: Vec<_> = self.text.keys().cloned().collect();
+        ports.sort();
+        for port in ports {
+            let text = &self.text[&port];
+            if !text.is_empty() {
+                writeln!(out, "port {}: {}", port, show(text))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `text` as UTF-8, control characters escaped
+fn show(text: &[u8]) -> String {
+    let mut shown = String::new();
+    for c in String::from_utf8_lossy(text).chars() {
+        if c.is_control() {
+            shown.extend(c.escape_default());
+        } else {
+            shown.push(c);
+        }
+    }
+    shown
+}
+
+/// What to do with the stimulus ports
+enum Mode {
+    /// Print them, with everything else
+    Print,
+    /// Write the bytes of one to the standard output
+    Port(u8),
+    /// Write the bytes of each to a file in a directory
+    Demultiplex(PathBuf),
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+
+    let (mode, path) = match parse(&args) {
+        Some(parsed) => parsed,
+        None => {
+            eprintln!("usage: stm32-itm [-p PORT | -d DIR] [FILE]");
+            process::exit(1);
+        }
+    };
+
+    let input: Box<dyn Read> = match path {
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdin()),
+    };
+
+    let stdout = io::stdout();
+    if let Err(e) = run(input, &mode, &mut stdout.lock()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+/// Mode and input file
+fn parse(args: &[String]) -> Option<(Mode, Option<&String>)> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("-p") => {
+            let port = args.get(1)?.parse().ok().filter(|port| *port < 32)?;
+            one_file(&args[2..]).map(|path| (Mode::Port(port), path))
+        }
+        Some("-d") => {
+            let dir = PathBuf::from(args.get(1)?);
+            one_file(&args[2..]).map(|path| (Mode::Demultiplex(dir), path))
+        }
+        Some(arg) if arg.starts_with('-') => None,
+        _ => one_file(args).map(|path| (Mode::Print, path)),
+    }
+}
+
+/// The input file, if any; `None` if there are too many
+fn one_file(args: &[String]) -> Option<Option<&String>> {
+    if args.len() > 1 {
+        None
+    } else {
+        Some(args.first())
+    }
+}
+
+/// Decodes `input`, writing what `mode` says to `out`
+fn run<R, W>(mut input: R, mode: &Mode, out: &mut W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut files: HashMap<u8, BufWriter<File>> = HashMap::new();
+    if let Mode::Demultiplex(dir) = mode {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut decoder = Decoder::default();
+    let mut printer = Printer::default();
+    let mut buffer = [0; 1024];
+    loop {
+        let n = match input.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        for byte in &buffer[..n] {
+            let packet = match decoder.feed(*byte) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            match (mode, &packet) {
+                (Mode::Print, _) => printer.packet(out, &packet, true)?,
+                (Mode::Port(port), Packet::Instrumentation { port: p, .. })
+                    if p != port => {}
+                (Mode::Port(_), Packet::Instrumentation { payload, .. }) => {
+                    // NOTE flushed right away, for pipes
+                    out.write_all(payload)?;
+                    out.flush()?;
+                }
+                (Mode::Port(_), _) => {}
+                (
+                    Mode::Demultiplex(dir),
+                    Packet::Instrumentation { port, payload },
+                ) => {
+                    let file = match files.entry(*port) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            let path = dir.join(format!("port-{}", port));
+                            entry.insert(BufWriter::new(File::create(path)?))
+                        }
+                    };
+                    file.write_all(payload)?;
+                    file.flush()?;
+                }
+                (Mode::Demultiplex(_), _) => {
+                    printer.packet(out, &packet, false)?
+                }
+            }
+        }
+    }
+
+    printer.flush(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe, run, Decoder, Mode};
+
+    /// What `stm32-itm` prints for `capture`
+    fn print(capture: &[u8]) -> String {
+        let mut out = vec![];
+        run(capture, &Mode::Print, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Descriptions of the packets of `capture`
+    fn packets(capture: &[u8]) -> Vec<Option<String>> {
+        let mut decoder = Decoder::default();
+        capture
+            .iter()
+            .filter_map(|byte| decoder.feed(*byte))
+            .map(|packet| describe(&packet))
+            .collect()
+    }
+
+    #[test]
+    fn sample_capture() {
+        assert_eq!(
+            print(include_bytes!("../captures/sample.itm")),
+            include_str!("../captures/sample.txt")
+        );
+    }
+
+    #[test]
+    fn extension_sources() {
+        let described = packets(&[0x08, 0x0C, 0x8C, 0x01, 0xFC, 0x7F]);
+
+        assert_eq!(
+            described,
+            vec![
+                Some("extension 0: 0x0".to_string()),
+                Some("extension 1: 0x0".to_string()),
+                Some("extension 1: 0x8".to_string()),
+                Some("extension 1: 0x3ff".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn sync_and_overflow() {
+        let described = packets(&[0, 0, 0, 0, 0, 0x80, 0x70]);
+
+        assert_eq!(
+            described,
+            vec![None, Some("overflow: packets were lost".to_string())]
+        );
+    }
+
+    #[test]
+    fn lines_wait_for_their_timestamp() {
+        // a local timestamp of 10 cycles, "hi\r\n" on port 0, then one of
+        // 100 cycles
+        let capture = [0xC0, 10, 0x03, b'h', b'i', b'\r', b'\n', 0xC0, 100];
+
+        assert_eq!(print(&capture), "[       110] port 0: hi\n");
+    }
+
+    #[test]
+    fn one_port() {
+        // "a" to port 1, "b" to port 2, "cd" to port 1
+        let capture = [0x09, b'a', 0x11, b'b', 0x0A, b'c', b'd'];
+
+        let mut out = vec![];
+        run(&capture[..], &Mode::Port(1), &mut out).unwrap();
+        assert_eq!(out, b"acd");
+    }
+}
@@ -0,0 +1,10 @@
+/* Format strings of `binlog!`: one byte symbols named after the strings. */
+/* The section isn't loaded, and starts at 0 so that the address of a */
+/* symbol is the index of its string. */
+SECTIONS
+{
+  .binlog 0 (INFO) :
+  {
+    *(.binlog);
+  }
+}
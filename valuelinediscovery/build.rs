@@ -4,14 +4,48 @@ use std::io::Write;
 use std::path::PathBuf;
 
 fn main() {
-    // Put the linker script somewhere the linker can find it
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(include_bytes!("memory.x"))
-        .unwrap();
-    println!("cargo:rustc-link-search={}", out.display());
-
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory-high-density.x");
+    println!("cargo:rerun-if-changed=memory-slot-a.x");
+    println!("cargo:rerun-if-changed=memory-slot-b.x");
+    println!("cargo:rerun-if-changed=binlog.x");
+    println!("cargo:rerun-if-changed=noinit.x");
+
+    // Crates with a layout of their own, like the bootloader, provide their
+    // own memory.x
+    if env::var_os("CARGO_FEATURE_CUSTOM_MEMORY").is_some() {
+        return;
+    }
+
+    let slot_a = env::var_os("CARGO_FEATURE_SLOT_A").is_some();
+    let slot_b = env::var_os("CARGO_FEATURE_SLOT_B").is_some();
+    let high_density = env::var_os("CARGO_FEATURE_HIGH_DENSITY").is_some();
+
+    // NOTE the bootloader layout is made of 1K pages
+    if high_density && (slot_a || slot_b) {
+        panic!("the bootloader slots are for medium density parts only");
+    }
+
+    // Applications started by the bootloader live in one of its slots. The
+    // `eeprom` pages at the end of the flash are twice as big on high
+    // density parts
+    let memory: &[u8] = if slot_a {
+        include_bytes!("memory-slot-a.x")
+    } else if slot_b {
+        include_bytes!("memory-slot-b.x")
+    } else if high_density {
+        include_bytes!("memory-high-density.x")
+    } else {
+        include_bytes!("memory.x")
+    };
+
+    // Put the linker script somewhere the linker can find it, with the
+    // sections of the `binlog` format strings and of `.noinit`
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let mut file = File::create(out.join("memory.x")).unwrap();
+    file.write_all(memory).unwrap();
+    file.write_all(include_bytes!("binlog.x")).unwrap();
+    file.write_all(include_bytes!("noinit.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
 }
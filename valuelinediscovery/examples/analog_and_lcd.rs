@@ -0,0 +1,144 @@
+//! Scans a potentiometer (PB0) and a battery divider (PB1) with the ADC and
+//! shows the raw readings on the LCD
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::ptr;
+
+use dsc::adc::{Adc, SampleTime};
+use dsc::lcd::Lcd;
+use dsc::stm32f100::interrupt::Dma1Channel1Irq;
+use dsc::stm32f100;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+extern crate numtoa;
+use numtoa::NumToA;
+
+// ADC channels of PB0 and PB1
+const CHANNELS: [u8; 2] = [8, 9];
+
+// Filled in by the DMA
+static mut SAMPLES: [u16; 2] = [0; 2];
+static mut READINGS: [u16; 2] = [0; 2];
+
+// RESOURCES
+peripherals!(stm32f100, {
+    ADC1: Peripheral {
+        register_block: Adc1,
+        ceiling: C1,
+    },
+    DMA1: Peripheral {
+        register_block: Dma1,
+        ceiling: C1,
+    },
+    GPIOB: Peripheral {
+        register_block: Gpiob,
+        ceiling: C0,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let adc1 = ADC1.access(priority, threshold);
+    let dma1 = DMA1.access(priority, threshold);
+    let gpiob = GPIOB.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+
+    // PB0 and PB1 as analog inputs
+    rcc.apb2enr.modify(|_, w| w.iopben().enabled());
+    gpiob
+        .crl
+        .modify(
+            |_, w| {
+                w.mode0().input().cnf0().push_pull() // really, analog
+                    .mode1().input().cnf1().push_pull()
+            },
+        );
+
+    let lcd = Lcd(&gpioc);
+    lcd.init(&rcc);
+    lcd.clear();
+    lcd.set_position(0, 0);
+    lcd.write(b"Potentiometer:");
+    lcd.set_position(2, 0);
+    lcd.write(b"Battery:");
+
+    let adc = Adc(&adc1);
+    adc.init(&rcc);
+    for channel in CHANNELS.iter() {
+        adc.set_sample_time(*channel, SampleTime::Cycles239_5).ok();
+    }
+
+    // NOTE(unsafe) `SAMPLES` is only read by `sampled`, once the DMA is done
+    if unsafe { adc.scan(&dma1, &rcc, &CHANNELS, &mut SAMPLES, true) }
+        .is_err()
+    {
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+    adc.start();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    loop {
+        let gpioc = GPIOC.access(priority, threshold);
+        let lcd = Lcd(&gpioc);
+        let mut bytes = [b' '; 8];
+
+        for (row, reading) in [1, 3].iter().zip(unsafe { READINGS.iter() }) {
+            let n = reading.numtoa(10, &mut bytes);
+            lcd.set_position(*row, 0);
+            lcd.write(b"    ");
+            lcd.set_position(*row, 0);
+            lcd.write(&bytes[n..]);
+        }
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    sampled: Task {
+        interrupt: Dma1Channel1Irq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+// A full scan has landed in `SAMPLES`
+fn sampled(_task: Dma1Channel1Irq, ref priority: P1, ref threshold: T1) {
+    let adc1 = ADC1.access(priority, threshold);
+    let dma1 = DMA1.access(priority, threshold);
+    let adc = Adc(&adc1);
+
+    if adc.clear_scan_flag(&dma1).is_ok() {
+        unsafe {
+            for i in 0..CHANNELS.len() {
+                READINGS[i] = ptr::read_volatile(&SAMPLES[i]);
+            }
+        }
+    } else {
+        // only reachable thru `rtfm::request(sampled)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -0,0 +1,106 @@
+//! Turns on the blue LED when the voltage on PB0 leaves the 1.0 V - 2.5 V
+//! window, without the CPU polling the ADC
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::adc::{Adc, Mode, Watch};
+use dsc::led::{self, LEDS};
+use dsc::stm32f100::interrupt::AdcIrq;
+use dsc::stm32f100;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+// ADC channel of PB0
+const CHANNEL: u8 = 8;
+
+// Window, in mV
+const LOW: u32 = 1_000;
+const HIGH: u32 = 2_500;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    ADC1: Peripheral {
+        register_block: Adc1,
+        ceiling: C1,
+    },
+    GPIOB: Peripheral {
+        register_block: Gpiob,
+        ceiling: C0,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let adc1 = ADC1.access(priority, threshold);
+    let gpiob = GPIOB.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+
+    // PB0 as analog input
+    rcc.apb2enr.modify(|_, w| w.iopben().enabled());
+    gpiob
+        .crl
+        .modify(|_, w| w.mode0().input().cnf0().push_pull()); // really, analog
+
+    let adc = Adc(&adc1);
+    adc.init(&rcc);
+    adc.enable_internal();
+    let vdda = adc.vdda().unwrap_or(3_300);
+
+    // Convert PB0 over and over, let the watchdog look at the results
+    adc.set_sequence(&[CHANNEL]).ok();
+    adc.set_mode(Mode::Continuous);
+    adc.watch_millivolts(Watch::Channel(CHANNEL), LOW, HIGH, vdda).ok();
+    adc.listen_watchdog(out_of_window);
+    adc.start();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+fn out_of_window(_value: u16) {
+    LEDS[0].on();
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    watchdog: Task {
+        interrupt: AdcIrq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn watchdog(_task: AdcIrq, ref priority: P1, ref threshold: T1) {
+    let adc1 = ADC1.access(priority, threshold);
+    let adc = Adc(&adc1);
+
+    if adc.handle_interrupt().is_err() {
+        // only reachable thru `rtfm::request(watchdog)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -0,0 +1,152 @@
+//! Logs every step of the rotary encoder from its poller, with deferred
+//! formatting, over ITM or the serial port
+//!
+//! Each record is 8 to 14 bytes and takes a few us to send over ITM. Decode
+//! them on the host with `tools/stm32-binlog`, which reads the format strings
+//! from the ELF file: `stm32-binlog ELF < /dev/ttyUSB0` for the serial port.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+#[macro_use]
+extern crate valuelinediscovery as dsc;
+
+use dsc::itm;
+use dsc::log::{Level, Sink};
+use dsc::rotary_encoder::{RotaryEncoder, State};
+use dsc::serial::Serial;
+use dsc::stm32f100::interrupt::Tim6DacIrq;
+use dsc::stm32f100;
+use dsc::timer::Timer6;
+use dsc::{binlog, profile};
+use rtfm::{P0, P1, T0, T1, TMax};
+
+pub const BAUD_RATE: u32 = 115_200; // bits per second
+const FREQUENCY: u32 = 400; // Hz
+const SINK: Sink = Sink::Itm; // `Sink::Serial` for USART1
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+    DCB: Peripheral {
+        register_block: Dcb,
+        ceiling: C0,
+    },
+    DWT: Peripheral {
+        register_block: Dwt,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C1,
+    },
+    ITM: Peripheral {
+        register_block: Itm,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM6: Peripheral {
+        register_block: Tim6,
+        ceiling: C1,
+    },
+    TPIU: Peripheral {
+        register_block: Tpiu,
+        ceiling: C0,
+    },
+    USART1: Peripheral {
+        register_block: Usart1,
+        ceiling: C0,
+    },
+});
+
+static mut POSITION: i32 = 0;
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dbg = DBG.access(priority, threshold);
+    let dcb = DCB.access(priority, threshold);
+    let dwt = DWT.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let itm = ITM.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim6 = TIM6.access(priority, threshold);
+    let tpiu = TPIU.access(priority, threshold);
+    let usart1 = USART1.access(priority, threshold);
+
+    match SINK {
+        Sink::Itm => {
+            itm::init(&dbg, &dcb, &itm, &tpiu, itm::DEFAULT_BAUD_RATE).ok();
+        }
+        Sink::Serial => Serial(&usart1).init(&gpioa, &rcc, BAUD_RATE),
+        Sink::None => {}
+    }
+    binlog::init(SINK, Some(Level::Trace));
+    profile::init(&dcb, &dwt);
+
+    RotaryEncoder(&gpioa).init(&rcc);
+
+    let timer6 = Timer6(&tim6);
+    timer6.init(&rcc, FREQUENCY);
+    timer6.resume();
+
+    binlog!(Level::Info, "polling the encoder at {} Hz", FREQUENCY);
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    poll: Task {
+        interrupt: Tim6DacIrq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn poll(_task: Tim6DacIrq, ref priority: P1, ref threshold: T1) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let tim6 = TIM6.access(priority, threshold);
+
+    if Timer6(&tim6).clear_update_flag().is_ok() {
+        let step = match RotaryEncoder(&gpioa).state() {
+            State::CW => 1,
+            State::CCW => -1,
+            State::BUTTON => {
+                binlog!(Level::Info, "button at {}", unsafe { POSITION });
+                return;
+            }
+            State::IDLE => return,
+        };
+
+        let position = unsafe {
+            POSITION += step;
+            POSITION
+        };
+
+        let start = profile::cycles();
+        binlog!(Level::Debug, "step {} to {}", step, position);
+        let cycles = profile::cycles().wrapping_sub(start);
+        binlog!(Level::Trace, "the record took {} cycles", cycles);
+    } else {
+        // only reachable thru `rtfm::request(poll)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
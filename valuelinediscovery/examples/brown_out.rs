@@ -0,0 +1,115 @@
+//! Saves a fault code into the backup registers as soon as VDD drops below
+//! 2.9 V, before the supply is gone
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::backup::Backup;
+use dsc::led::{self, LEDS};
+use dsc::power::{Crossing, Power, Threshold};
+use dsc::stm32f100::interrupt::PvdIrq;
+use dsc::stm32f100;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+// Backup register holding the last fault code
+const LAST_FAULT: usize = 1;
+const BROWN_OUT: u16 = 0xB0;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    BKP: Peripheral {
+        register_block: Bkp,
+        ceiling: C1,
+    },
+    EXTI: Peripheral {
+        register_block: Exti,
+        ceiling: C1,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    PWR: Peripheral {
+        register_block: Pwr,
+        ceiling: C1,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let bkp = BKP.access(priority, threshold);
+    let exti = EXTI.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+
+    let backup = Backup(&bkp);
+    backup.init(&pwr, &rcc);
+    if backup.read(LAST_FAULT).ok() == Some(BROWN_OUT) {
+        // the last run ended with a brown out
+        LEDS[1].on();
+        backup.write(LAST_FAULT, 0).ok();
+    }
+
+    let power = Power(&pwr);
+    power.init(&rcc);
+    power.enable_pvd(&exti, Threshold::V2_9, Crossing::Both);
+    power.listen_pvd(&exti, supply_changed);
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+fn supply_changed(low: bool) {
+    if low {
+        LEDS[0].on();
+    } else {
+        LEDS[0].off();
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    brown_out: Task {
+        interrupt: PvdIrq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn brown_out(_task: PvdIrq, ref priority: P1, ref threshold: T1) {
+    let bkp = BKP.access(priority, threshold);
+    let exti = EXTI.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let power = Power(&pwr);
+
+    if power.handle_pvd_interrupt(&exti).is_ok() {
+        if power.is_below_threshold() {
+            // emergency save
+            Backup(&bkp).write(LAST_FAULT, BROWN_OUT).ok();
+        }
+    } else {
+        // only reachable thru `rtfm::request(brown_out)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -0,0 +1,145 @@
+//! Prints the date and time on the OpenOCD console once per second, and
+//! counts boots in the backup registers
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::backup::Backup;
+use dsc::rtc::{Clock, DateTime, Event, Rtc};
+use dsc::stm32f100::interrupt::RtcWkupIrq;
+use dsc::stm32f100;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+// Persisted in the backup registers
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Record {
+    boots: u16,
+    last_fault: u16,
+}
+
+// RESOURCES
+peripherals!(stm32f100, {
+    BKP: Peripheral {
+        register_block: Bkp,
+        ceiling: C0,
+    },
+    DCB: Peripheral {
+        register_block: Dcb,
+        ceiling: C0,
+    },
+    DWT: Peripheral {
+        register_block: Dwt,
+        ceiling: C0,
+    },
+    EXTI: Peripheral {
+        register_block: Exti,
+        ceiling: C1,
+    },
+    PWR: Peripheral {
+        register_block: Pwr,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    RTC: Peripheral {
+        register_block: Rtc,
+        ceiling: C1,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let bkp = BKP.access(priority, threshold);
+    let dcb = DCB.access(priority, threshold);
+    let dwt = DWT.access(priority, threshold);
+    let exti = EXTI.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let rtc = RTC.access(priority, threshold);
+
+    let backup = Backup(&bkp);
+    backup.init(&pwr, &rcc);
+
+    let rtc = Rtc(&rtc);
+    match rtc.init(&pwr, &rcc, Clock::Lsi) {
+        Ok(true) => hprintln!("RTC kept running"),
+        Ok(false) => {
+            // NOTE a fresh RTC also means fresh backup registers
+            let start = DateTime {
+                year: 2017,
+                month: 1,
+                day: 1,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            };
+            rtc.set_datetime(&start).ok();
+
+            // NOTE only here: recalibrating a running RTC shifts its time
+            let lsi = rtc.calibrate_lsi(&dcb, &dwt);
+            hprintln!("LSI: {} Hz", lsi);
+        }
+        Err(_) => hprintln!("RTC failed to start"),
+    }
+
+    let mut record = backup
+        .load::<Record>()
+        .unwrap_or(Record { boots: 0, last_fault: 0 });
+    record.boots += 1;
+    backup.store(&record).ok();
+    hprintln!("Boot #{}", record.boots);
+
+    rtc.listen(&exti, Event::Second);
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    tick: Task {
+        interrupt: RtcWkupIrq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn tick(_task: RtcWkupIrq, ref priority: P1, ref threshold: T1) {
+    let exti = EXTI.access(priority, threshold);
+    let rtc = RTC.access(priority, threshold);
+    let rtc = Rtc(&rtc);
+
+    if rtc.clear_flag(&exti, Event::Second).is_ok() {
+        let now = rtc.datetime();
+        hprintln!(
+            "{}-{:02}-{:02} {:02}:{:02}:{:02}",
+            now.year,
+            now.month,
+            now.day,
+            now.hour,
+            now.minute,
+            now.second
+        );
+    } else {
+        // only reachable thru `rtfm::request(tick)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -11,6 +11,7 @@ extern crate cortex_m_rtfm as rtfm;
 extern crate valuelinediscovery as dsc;
 
 use dsc::led::{self, LEDS};
+use dsc::power;
 use dsc::serial::Serial;
 use dsc::stm32f100::interrupt::{Usart1Irq,Tim7Irq};
 use dsc::stm32f100;
@@ -26,6 +27,10 @@ pub const BAUD_RATE: u32 = 115_200; // bits per second
 // RESOURCES
 // have to register all periphs that we're using
 peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
     GPIOA:  Peripheral {
         register_block: Gpioa,
         ceiling: C0, // kinda like a priority
@@ -54,6 +59,11 @@ fn init(ref priority: P0, threshold: &TMax) {
 
     // common
     let rcc = RCC.access(priority, threshold);
+    let dbg = DBG.access(priority, threshold);
+
+    // keep JTAG/SWD alive while sleeping in `idle`, release builds too:
+    // `wfi` would freeze it otherwise
+    power::debug(&dbg, true);
 
     // stuff for serial loopback
     let gpioa = GPIOA.access(priority, threshold);
@@ -77,7 +87,7 @@ fn init(ref priority: P0, threshold: &TMax) {
 
 fn idle(_priority: P0, _threshold: T0) -> ! {
     loop {
-        //rtfm::wfi(); // this freezes JTAG, so don't
+        rtfm::wfi();
     }
 }
 
@@ -0,0 +1,143 @@
+//! Crashes on a press of the user button, a different way each time, and
+//! explains the previous crash over serial at boot
+//!
+//! The fault handler logs the fault over serial too, before it resets the
+//! device. With gdb connected, it stops at a breakpoint first: `continue`.
+
+#![feature(asm)]
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::fmt::{self, Write};
+use core::{mem, ptr};
+
+use dsc::button::Button;
+use dsc::fault;
+use dsc::log::{self, Level, Sink};
+use dsc::serial::Serial;
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+pub const BAUD_RATE: u32 = 115_200; // bits per second
+
+// Unmapped: past the end of the RAM
+const NOWHERE: u32 = 0x2000_4000;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SCB: Peripheral {
+        register_block: Scb,
+        ceiling: C0,
+    },
+    USART1: Peripheral {
+        register_block: Usart1,
+        ceiling: C0,
+    },
+});
+
+// The way to crash next; it survives the reset
+#[link_section = ".noinit"]
+static mut NEXT: u32 = 0;
+
+
+// Blocking writer
+struct Console<'a>(Serial<'a>);
+
+impl<'a> Write for Console<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            while self.0.write(byte).is_err() {}
+        }
+        Ok(())
+    }
+}
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let scb = SCB.access(priority, threshold);
+    let usart1 = USART1.access(priority, threshold);
+
+    let serial = Serial(&usart1);
+    serial.init(&gpioa, &rcc, BAUD_RATE);
+    log::init(Sink::Serial, Some(Level::Info));
+
+    fault::init(&scb);
+    Button(&gpioa).init(&rcc);
+
+    let mut console = Console(serial);
+    match fault::last_fault() {
+        Some(fault) => {
+            writeln!(console, "the last run ended in a fault:").ok();
+            fault::report(&mut console, &fault).ok();
+        }
+        None => {
+            // NOTE `NEXT` holds garbage after a power on
+            unsafe { NEXT = 0 }
+            writeln!(console, "no fault recorded").ok();
+        }
+    }
+    writeln!(console, "press the button to crash").ok();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    let gpioa = GPIOA.access(priority, threshold);
+
+    while !Button(&gpioa).is_pressed() {}
+
+    let next = unsafe {
+        NEXT = (NEXT + 1) % 5;
+        NEXT
+    };
+
+    unsafe {
+        match next {
+            // imprecise bus error: the write is buffered
+            0 => ptr::write_volatile(NOWHERE as *mut u32, 0),
+            // precise bus error
+            1 => {
+                ptr::read_volatile(NOWHERE as *const u32);
+            }
+            // undefined instruction
+            2 => asm!("udf #0" :::: "volatile"),
+            // invalid state: a Thumb function's address has bit 0 set
+            3 => {
+                let f: fn() = mem::transmute(0x0800_0000_usize);
+                f()
+            }
+            // divide by zero, trapped since `fault::init`
+            _ => {
+                asm!("udiv r0, r0, r1"
+                     :
+                     : "{r0}"(1), "{r1}"(0)
+                     : "r0"
+                     : "volatile")
+            }
+        }
+    }
+
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
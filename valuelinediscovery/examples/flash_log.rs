@@ -0,0 +1,78 @@
+//! Counts boots in the last flash page and prints the count on the OpenOCD
+//! console
+//!
+//! Every boot appends one half-word to the page; the page is only erased
+//! once it's full.
+//!
+//! NOTE the last page also holds one of the `eeprom` banks, so this wipes
+//! the emulated EEPROM.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::ptr;
+
+use dsc::flash::{self, Flash, PAGE_SIZE};
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+// RESOURCES
+peripherals!(stm32f100, {
+    FLASH: Peripheral {
+        register_block: Flash,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let flash = FLASH.access(priority, threshold);
+    let flash = Flash(&flash);
+
+    let page = flash::page_address(flash::pages() - 1);
+
+    // find the first erased half-word
+    let mut offset = 0;
+    let mut boots = 0;
+    while offset < PAGE_SIZE {
+        let half_word =
+            unsafe { ptr::read_volatile((page + offset) as *const u16) };
+        if half_word == 0xFFFF {
+            break;
+        }
+        boots = half_word;
+        offset += 2;
+    }
+    boots += 1;
+
+    flash.unlock();
+    if offset == PAGE_SIZE {
+        flash.erase_page(page).ok();
+        offset = 0;
+    }
+    match flash.program(page + offset, boots) {
+        Ok(()) => hprintln!("boot #{}", boots),
+        Err(_) => hprintln!("couldn't program the flash"),
+    }
+    flash.lock();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
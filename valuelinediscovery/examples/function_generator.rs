@@ -0,0 +1,104 @@
+//! Bench function generator
+//!
+//! - PA4 - 100 Hz sine, played from a table by DMA, paced by TIM6
+//! - PA5 - triangle from the DAC's own generator, paced by TIM7
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::dac::{sample_rate, Channel, Dac, Format, Trigger, Wave, SINE};
+use dsc::stm32f100;
+use dsc::timer::{Timer, Timer6};
+use rtfm::{P0, T0, TMax};
+
+const FREQUENCY: u32 = 100; // Hz
+const TRIANGLE_STEP_RATE: u32 = 8_000; // Hz
+
+// RESOURCES
+peripherals!(stm32f100, {
+    AFIO: Peripheral {
+        register_block: Afio,
+        ceiling: C0,
+    },
+    DAC: Peripheral {
+        register_block: Dac,
+        ceiling: C0,
+    },
+    DMA1: Peripheral {
+        register_block: Dma1,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM6: Peripheral {
+        register_block: Tim6,
+        ceiling: C0,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let afio = AFIO.access(priority, threshold);
+    let dac = DAC.access(priority, threshold);
+    let dma1 = DMA1.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim6 = TIM6.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    let dac = Dac(&dac);
+    dac.init(&gpioa, &rcc);
+
+    // Sine on channel 1
+    let timer6 = Timer6(&tim6);
+    timer6.init(&rcc, sample_rate(FREQUENCY, SINE.len()));
+    timer6.trigger_on_update();
+    if dac.play(&afio, &dma1, &rcc, Channel::One, Format::Right12,
+                Trigger::Tim6Trgo, &SINE)
+        .is_err()
+    {
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+    dac.enable(Channel::One, true);
+
+    // Triangle, 1023 counts peak-to-peak, centred around mid-scale
+    let timer = Timer(&tim7);
+    timer.init(&rcc, TRIANGLE_STEP_RATE);
+    timer.trigger_on_update();
+    dac.write(Channel::Two, Format::Right12, 1_536);
+    dac.set_trigger(Channel::Two, Some(Trigger::Tim7Trgo));
+    dac.set_wave(Channel::Two, Wave::Triangle(10)).ok();
+    dac.enable(Channel::Two, true);
+
+    timer6.resume();
+    timer.resume();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
@@ -0,0 +1,153 @@
+//! Logs the user button and a heartbeat, over ITM or the serial port
+//!
+//! Over ITM the records of the `heartbeat` module go to stimulus port 1, the
+//! rest to port 0. Capture them with
+//! `monitor tpiu config internal itm.fifo uart off 8000000 2000000` and
+//! `monitor itm ports on` in `.gdbinit`.
+//!
+//! Build with `--features max-level-info` and the `trace!` records of the
+//! heartbeat disappear from the binary.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+#[macro_use]
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::itm;
+use dsc::log::{self, Level, Rule, Sink};
+use dsc::serial::Serial;
+use dsc::stm32f100;
+use dsc::time::{self, Deadline, Duration};
+use rtfm::{P0, T0, TMax};
+
+pub const BAUD_RATE: u32 = 115_200; // bits per second
+const SINK: Sink = Sink::Itm; // `Sink::Serial` for USART1
+
+static RULES: [Rule; 1] = [
+    Rule {
+        module: "log::heartbeat",
+        level: Some(Level::Trace),
+        port: 1,
+    },
+];
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+    DCB: Peripheral {
+        register_block: Dcb,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    ITM: Peripheral {
+        register_block: Itm,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SYST: Peripheral {
+        register_block: Syst,
+        ceiling: C0,
+    },
+    TPIU: Peripheral {
+        register_block: Tpiu,
+        ceiling: C0,
+    },
+    USART1: Peripheral {
+        register_block: Usart1,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dbg = DBG.access(priority, threshold);
+    let dcb = DCB.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let itm = ITM.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let syst = SYST.access(priority, threshold);
+    let tpiu = TPIU.access(priority, threshold);
+    let usart1 = USART1.access(priority, threshold);
+
+    Button(&gpioa).init(&rcc);
+    time::init(&syst, time::DEFAULT_PERIOD).ok();
+
+    match SINK {
+        Sink::Itm => {
+            itm::init(&dbg, &dcb, &itm, &tpiu, itm::DEFAULT_BAUD_RATE).ok();
+        }
+        Sink::Serial => Serial(&usart1).init(&gpioa, &rcc, BAUD_RATE),
+        Sink::None => {}
+    }
+
+    log::init(SINK, Some(Level::Info));
+    log::set_rules(&RULES);
+
+    info!("up and running");
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    let gpioa = GPIOA.access(priority, threshold);
+    let button = Button(&gpioa);
+
+    let period = Duration::from_secs(1);
+    let mut beat = Deadline::after(period);
+    let mut pressed = None;
+
+    loop {
+        if beat.has_expired() {
+            beat.extend(period);
+            heartbeat::beat();
+        }
+
+        match (button.is_pressed(), pressed) {
+            (true, None) => {
+                pressed = Some(time::now());
+                debug!("button pressed"); // below the default level
+            }
+            (false, Some(since)) => {
+                let held: Duration = since.elapsed();
+                pressed = None;
+
+                if held.as_secs() >= 2 {
+                    warn!("button held for {} ms", held.as_millis());
+                } else {
+                    info!("button held for {} ms", held.as_millis());
+                }
+            }
+            _ => {}
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+mod heartbeat {
+    use dsc::time;
+
+    pub fn beat() {
+        trace!("{} s", time::now().as_micros() / 1_000_000);
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
@@ -0,0 +1,91 @@
+//! Prints the option bytes on the OpenOCD console; hold the user button
+//! while resetting to increment the first user data byte
+//!
+//! The write protection of the first sector is left as is. NOTE setting
+//! `read_protection` locks the debugger out of the flash; `stm32f1x unlock 0`
+//! in OpenOCD lifts it, erasing the whole flash.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::flash::{self, Flash};
+use dsc::reset;
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+// RESOURCES
+peripherals!(stm32f100, {
+    FLASH: Peripheral {
+        register_block: Flash,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SCB: Peripheral {
+        register_block: Scb,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let flash = FLASH.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let scb = SCB.access(priority, threshold);
+
+    let flash = Flash(&flash);
+    let mut options = flash.option_bytes();
+
+    hprintln!("read protection: {}", options.read_protection);
+    hprintln!(
+        "boot sector write protected: {}",
+        options.write_protection & flash::sector(flash::START) != 0
+    );
+    hprintln!("hardware watchdog: {}", options.hardware_watchdog);
+    hprintln!("data: {:?}", options.data);
+
+    let button = Button(&gpioa);
+    button.init(&rcc);
+    if button.is_pressed() {
+        options.data[0] = options.data[0].wrapping_add(1);
+
+        flash.unlock();
+        let result = flash.program_option_bytes(&options);
+        flash.lock();
+
+        if result.is_ok() {
+            // load the new option bytes
+            reset::system_reset(&scb);
+        } else {
+            hprintln!("couldn't program the option bytes");
+        }
+    }
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
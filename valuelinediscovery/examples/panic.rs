@@ -0,0 +1,96 @@
+//! Panics on the third press of the user button, and prints the previous
+//! `panic!` over serial at boot
+//!
+//! Build it with "panic-store" and a strategy, e.g. `make release`, or
+//! `make PANIC=panic-blink,panic-store` to read the line number off the
+//! LEDs.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::fmt::{self, Write};
+
+use dsc::button::Button;
+use dsc::log::{self, Level, Sink};
+use dsc::panic;
+use dsc::serial::Serial;
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+pub const BAUD_RATE: u32 = 115_200; // bits per second
+
+// RESOURCES
+peripherals!(stm32f100, {
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    USART1: Peripheral {
+        register_block: Usart1,
+        ceiling: C0,
+    },
+});
+
+
+// Blocking writer
+struct Console<'a>(Serial<'a>);
+
+impl<'a> Write for Console<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            while self.0.write(byte).is_err() {}
+        }
+        Ok(())
+    }
+}
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let usart1 = USART1.access(priority, threshold);
+
+    let serial = Serial(&usart1);
+    serial.init(&gpioa, &rcc, BAUD_RATE);
+    log::init(Sink::Serial, Some(Level::Info));
+
+    Button(&gpioa).init(&rcc);
+
+    let mut console = Console(serial);
+    match panic::last_panic() {
+        Some(panic) => writeln!(console, "the last run {}", panic).ok(),
+        None => writeln!(console, "no panic recorded").ok(),
+    };
+    writeln!(console, "press the button to panic").ok();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    let gpioa = GPIOA.access(priority, threshold);
+
+    let mut presses = 0;
+    loop {
+        if Button(&gpioa).is_pressed() {
+            presses += 1;
+            if presses == 3 {
+                panic!("the button was pressed {} times", presses);
+            }
+        }
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
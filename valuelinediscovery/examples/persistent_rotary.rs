@@ -0,0 +1,153 @@
+//! Shows the rotary encoder position on the LCD and keeps it across power
+//! cycles in the emulated EEPROM
+//!
+//! The position is saved when the user button is pressed, rather than on
+//! every step, to spare the flash.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+extern crate numtoa;
+
+use dsc::button::Button;
+use dsc::eeprom::Eeprom;
+use dsc::flash::Flash;
+use dsc::lcd::Lcd;
+use dsc::rotary_encoder::{RotaryEncoder, State};
+use dsc::stm32f100::interrupt::Tim6DacIrq;
+use dsc::stm32f100;
+use dsc::timer::Timer6;
+use numtoa::NumToA;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+const FREQUENCY: u32 = 400; // Hz
+
+// Virtual address of the encoder position
+const POSITION: u16 = 0x0001;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    FLASH: Peripheral {
+        register_block: Flash,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C1,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM6: Peripheral {
+        register_block: Tim6,
+        ceiling: C1,
+    },
+});
+
+static mut COUNT: u32 = 0;
+static mut SAVE: bool = false;
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let flash = FLASH.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim6 = TIM6.access(priority, threshold);
+
+    let flash = Flash(&flash);
+    flash.unlock();
+    let eeprom = Eeprom(flash);
+    if eeprom.init().is_ok() {
+        unsafe { COUNT = eeprom.read(POSITION).unwrap_or(0) }
+    }
+
+    Button(&gpioa).init(&rcc);
+    RotaryEncoder(&gpioa).init(&rcc);
+
+    let lcd = Lcd(&gpioc);
+    lcd.init(&rcc);
+    lcd.clear();
+    lcd.set_position(0, 0);
+    lcd.write(b"Position:");
+
+    let timer6 = Timer6(&tim6);
+    timer6.init(&rcc, FREQUENCY);
+    timer6.resume();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    loop {
+        let flash = FLASH.access(priority, threshold);
+        let gpioc = GPIOC.access(priority, threshold);
+        let lcd = Lcd(&gpioc);
+
+        let count = unsafe { COUNT };
+        let mut bytes = [b' '; 10];
+        count.numtoa(10, &mut bytes);
+        lcd.set_position(1, 0);
+        lcd.write(&bytes);
+
+        // NOTE the flash write stalls the CPU, so it's done here rather than
+        // in the task
+        if unsafe { SAVE } {
+            unsafe { SAVE = false }
+            let saved = Eeprom(Flash(&flash)).write(POSITION, count).is_ok();
+            lcd.set_position(2, 0);
+            lcd.write(if saved { b"saved " } else { b"failed" });
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    inputs: Task {
+        interrupt: Tim6DacIrq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn inputs(_task: Tim6DacIrq, ref priority: P1, ref threshold: T1) {
+    static mut PRESSED: bool = false;
+
+    let gpioa = GPIOA.access(priority, threshold);
+    let tim6 = TIM6.access(priority, threshold);
+
+    if Timer6(&tim6).clear_update_flag().is_ok() {
+        let pressed = Button(&gpioa).is_pressed();
+        unsafe {
+            // save on the press, not while the button is held
+            if pressed && !PRESSED {
+                SAVE = true;
+            }
+            PRESSED = pressed;
+
+            match RotaryEncoder(&gpioa).state() {
+                State::CW => COUNT = COUNT.wrapping_add(1),
+                State::CCW => COUNT = COUNT.wrapping_sub(1),
+                _ => {}
+            }
+        }
+    } else {
+        // only reachable thru `rtfm::request(inputs)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
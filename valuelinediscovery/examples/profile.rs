@@ -0,0 +1,119 @@
+//! Measures the interrupt latency and the run time of a periodic task with
+//! the cycle counter, and prints them on the OpenOCD console every second
+//!
+//! TIM7 counts core clock cycles, so its counter, read at the start of the
+//! task, is the number of cycles since the update event: the latency.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::profile::{self, Probe, Stopwatch};
+use dsc::stm32f100::interrupt::Tim7Irq;
+use dsc::stm32f100;
+use dsc::timer::Timer;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+const FREQUENCY: u32 = 1_000; // Hz, low enough for TIM7 to count cycles
+const WORK: u32 = 2_000; // ns
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DCB: Peripheral {
+        register_block: Dcb,
+        ceiling: C0,
+    },
+    DWT: Peripheral {
+        register_block: Dwt,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C1,
+    },
+});
+
+static mut LATENCY: Probe = Probe::new("latency");
+static mut TASK: Probe = Probe::new("task");
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dcb = DCB.access(priority, threshold);
+    let dwt = DWT.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    profile::init(&dcb, &dwt);
+
+    let timer = Timer(&tim7);
+    timer.init(&rcc, FREQUENCY);
+    timer.resume();
+}
+
+
+fn idle(_priority: P0, ref threshold: T0) -> ! {
+    let mut second = Stopwatch::start();
+
+    loop {
+        if second.cycles() >= profile::CLOCK {
+            second = Stopwatch::start();
+
+            // NOTE copy the probes with the task masked, then print them
+            // with the task running
+            let (latency, task) = threshold.raise(&TIM7, |_| unsafe {
+                let probes = (LATENCY, TASK);
+                LATENCY.reset();
+                TASK.reset();
+                probes
+            });
+
+            hprintln!("{}", latency);
+            hprintln!("{}", task);
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    periodic: Task {
+        interrupt: Tim7Irq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn periodic(_task: Tim7Irq, ref priority: P1, ref threshold: T1) {
+    let tim7 = TIM7.access(priority, threshold);
+
+    let latency = u32::from(tim7.cnt.read().cnt().bits());
+    let stopwatch = Stopwatch::start();
+
+    if Timer(&tim7).clear_update_flag().is_ok() {
+        // stand-in for some real work
+        profile::delay_ns(WORK);
+
+        unsafe {
+            LATENCY.record(latency);
+            TASK.record(stopwatch.cycles());
+        }
+    } else {
+        // only reachable thru `rtfm::request(periodic)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -0,0 +1,95 @@
+//! Prints why the device rebooted, and how the previous run ended, over
+//! serial at boot
+//!
+//! Try the reset button, a power cycle or `monitor reset` in gdb. Needs the
+//! "crash-record" feature: `make build FEATURES=crash-record`
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::fmt::{self, Write};
+
+use dsc::backup;
+use dsc::reset;
+use dsc::serial::Serial;
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+pub const BAUD_RATE: u32 = 115_200; // bits per second
+
+// RESOURCES
+peripherals!(stm32f100, {
+    BKP: Peripheral {
+        register_block: Bkp,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    PWR: Peripheral {
+        register_block: Pwr,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    USART1: Peripheral {
+        register_block: Usart1,
+        ceiling: C0,
+    },
+});
+
+
+// Blocking writer
+struct Console<'a>(Serial<'a>);
+
+impl<'a> Write for Console<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            while self.0.write(byte).is_err() {}
+        }
+        Ok(())
+    }
+}
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let bkp = BKP.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let usart1 = USART1.access(priority, threshold);
+
+    let cause = reset::reset_cause(&rcc);
+
+    let serial = Serial(&usart1);
+    serial.init(&gpioa, &rcc, BAUD_RATE);
+    let mut console = Console(serial);
+
+    writeln!(console, "reset: {}", cause).ok();
+
+    backup::unlock(&pwr, &rcc);
+    if let Some(crash) = reset::last_crash(&bkp) {
+        writeln!(console, "last run {}", crash).ok();
+    }
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
@@ -0,0 +1,79 @@
+//! Prints the unique ID, a MAC address derived from it, the flash size and
+//! the device ID on the OpenOCD console
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use core::str;
+
+use dsc::signature;
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dbg = DBG.access(priority, threshold);
+
+    let uid = signature::uid();
+    let serial_number = uid.serial_number();
+    let mac = uid.mac_address();
+
+    hprintln!("unique ID: {}", uid);
+    hprintln!(
+        "serial number: {}",
+        str::from_utf8(&serial_number).unwrap_or("?")
+    );
+    hprintln!(
+        "MAC address: {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    );
+    hprintln!(
+        "flash: {} KiB ({} density)",
+        signature::flash_size(),
+        if signature::is_high_density() {
+            "high"
+        } else {
+            "low/medium"
+        }
+    );
+
+    match signature::id_code(&dbg) {
+        Some(id) => {
+            hprintln!("device: {}, revision {:#06x}", id.device, id.revision)
+        }
+        None => hprintln!("device: unknown (IDCODE needs a debugger)"),
+    }
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
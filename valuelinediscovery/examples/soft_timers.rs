@@ -0,0 +1,116 @@
+//! Blinks both LEDs at different rates with software timers driven by
+//! SysTick; pressing the user button freezes the blue LED for 3 seconds
+//!
+//! The blinking timers deliver callbacks; the timeout a flag, which `idle`
+//! polls.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::led::{self, LEDS};
+use dsc::soft_timer::{self, Mode, SoftTimer};
+use dsc::stm32f100;
+use dsc::time;
+use rtfm::{P0, T0, TMax};
+
+// Periods, in ticks of 1 ms
+const BLUE: u32 = 250;
+const GREEN: u32 = 1_000;
+const FREEZE: u32 = 3_000;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SYST: Peripheral {
+        register_block: Syst,
+        ceiling: C0,
+    },
+});
+
+static mut BLUE_ON: bool = false;
+static mut GREEN_ON: bool = false;
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let syst = SYST.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+    Button(&gpioa).init(&rcc);
+
+    time::listen(soft_timer::tick);
+    time::init(&syst, time::DEFAULT_PERIOD).ok();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    let gpioa = GPIOA.access(priority, threshold);
+    let button = Button(&gpioa);
+
+    let blue = SoftTimer::new(Mode::Periodic, BLUE, Some(toggle_blue));
+    let green = SoftTimer::new(Mode::Periodic, GREEN, Some(toggle_green));
+    let freeze = SoftTimer::new(Mode::OneShot, FREEZE, None);
+
+    let (blue, green, freeze) = match (blue, green, freeze) {
+        (Ok(blue), Ok(green), Ok(freeze)) => (blue, green, freeze),
+        _ => loop {},
+    };
+    blue.start();
+    green.start();
+
+    let mut pressed = false;
+    loop {
+        // freeze on the press, not while the button is held
+        if button.is_pressed() && !pressed {
+            blue.stop();
+            freeze.restart();
+        }
+        pressed = button.is_pressed();
+
+        if freeze.clear_flag().is_ok() {
+            blue.start();
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+fn toggle_blue(_: &SoftTimer) {
+    unsafe {
+        BLUE_ON = !BLUE_ON;
+        if BLUE_ON { LEDS[0].on() } else { LEDS[0].off() }
+    }
+}
+
+fn toggle_green(_: &SoftTimer) {
+    unsafe {
+        GREEN_ON = !GREEN_ON;
+        if GREEN_ON { LEDS[1].on() } else { LEDS[1].off() }
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
@@ -0,0 +1,85 @@
+//! Lights the LEDs for a moment, then drops into Standby mode until the blue
+//! user button (PA0, WKUP) is pressed, which starts the program over
+
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use cortex_m::asm;
+use dsc::led::{self, LEDS};
+use dsc::power::{self, Power};
+use dsc::stm32f100;
+use rtfm::{P0, T0, TMax};
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    PWR: Peripheral {
+        register_block: Pwr,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SCB: Peripheral {
+        register_block: Scb,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dbg = DBG.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+
+    // keep JTAG/SWD alive in Standby, release builds too
+    power::debug(&dbg, true);
+
+    let power = Power(&pwr);
+    power.init(&rcc);
+    if power.was_in_standby() {
+        hprintln!("Woke up from Standby");
+        power.clear_standby_flag();
+    }
+
+    led::init(&gpioc, &rcc);
+    for led in LEDS.iter() {
+        led.on();
+    }
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    // leave the LEDs on for a bit
+    for _ in 0..1_000_000 {
+        asm::nop();
+    }
+
+    let pwr = PWR.access(priority, threshold);
+    let scb = SCB.access(priority, threshold);
+
+    let power = Power(&pwr);
+    power.enable_wakeup_pin();
+    power.standby(&scb)
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
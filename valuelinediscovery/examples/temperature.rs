@@ -0,0 +1,87 @@
+//! Prints the die temperature and the supply voltage on the OpenOCD console
+//! once per second
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::adc::Adc;
+use dsc::stm32f100::interrupt::Tim7Irq;
+use dsc::stm32f100;
+use dsc::timer::Timer;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+const FREQUENCY: u32 = 1; // Hz
+
+// RESOURCES
+peripherals!(stm32f100, {
+    ADC1: Peripheral {
+        register_block: Adc1,
+        ceiling: C1,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C1,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let adc1 = ADC1.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    let adc = Adc(&adc1);
+    adc.init(&rcc);
+    adc.enable_internal();
+
+    let timer = Timer(&tim7);
+    timer.init(&rcc, FREQUENCY);
+    timer.resume();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    periodic: Task {
+        interrupt: Tim7Irq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn periodic(_task: Tim7Irq, ref priority: P1, ref threshold: T1) {
+    let adc1 = ADC1.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+    let adc = Adc(&adc1);
+    let timer = Timer(&tim7);
+
+    if timer.clear_update_flag().is_ok() {
+        if let (Ok(vdda), Ok(temperature)) = (adc.vdda(), adc.temperature()) {
+            hprintln!("VDDA: {} mV, T: {} mC", vdda, temperature);
+        }
+    } else {
+        // only reachable thru `rtfm::request(periodic)
+        #[cfg(debug_assertion)]
+        unreachable!()
+    }
+}
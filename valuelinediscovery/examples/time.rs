@@ -0,0 +1,99 @@
+//! Blinks the blue LED off a deadline and prints how long the user button
+//! was held on the OpenOCD console
+//!
+//! The green LED is lit for 100 ms with a blocking delay after each press.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m;
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::led::{self, LEDS};
+use dsc::stm32f100;
+use dsc::time::{self, Deadline, Duration};
+use rtfm::{P0, T0, TMax};
+
+const BLINK: u64 = 500; // ms
+
+// RESOURCES
+peripherals!(stm32f100, {
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C0,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SYST: Peripheral {
+        register_block: Syst,
+        ceiling: C0,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let syst = SYST.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+    Button(&gpioa).init(&rcc);
+
+    time::init(&syst, time::DEFAULT_PERIOD).ok();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    let gpioa = GPIOA.access(priority, threshold);
+    let button = Button(&gpioa);
+
+    let period = Duration::from_millis(BLINK);
+    let mut blink = Deadline::after(period);
+    let mut on = false;
+    let mut pressed = None;
+
+    loop {
+        if blink.has_expired() {
+            // NOTE extending the deadline, rather than starting a new one,
+            // keeps the blinking from drifting
+            blink.extend(period);
+            on = !on;
+            if on { LEDS[0].on() } else { LEDS[0].off() }
+        }
+
+        match (button.is_pressed(), pressed) {
+            (true, None) => pressed = Some(time::now()),
+            (false, Some(since)) => {
+                let held: Duration = since.elapsed();
+                pressed = None;
+
+                hprintln!("held for {} ms", held.as_millis());
+
+                LEDS[1].on();
+                time::delay_ms(100);
+                LEDS[1].off();
+            }
+            _ => {}
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {});
@@ -0,0 +1,159 @@
+//! Application for the A/B bootloader: confirms itself after running for
+//! a while, see `doc/bootloader.md`
+//!
+//! Build it with `make image NAME=update_confirm SLOT=a` (or `b`). It blinks
+//! the blue LED when it runs from slot A and the green LED when it runs from
+//! slot B, and feeds the IWDG started by the bootloader. After `CONFIRM`
+//! seconds it confirms the image and both LEDs light up for a moment.
+//!
+//! Holding the user button before then makes it stop feeding the IWDG, like
+//! a broken image would: after a few resets the bootloader falls back to the
+//! image in the other slot.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::flash::Flash;
+use dsc::led::{self, LEDS};
+use dsc::stm32f100::interrupt::Tim7Irq;
+use dsc::stm32f100;
+use dsc::timer::Timer;
+use dsc::update::{self, Slot};
+use dsc::watchdog::IndependentWatchdog;
+use rtfm::{P0, P1, T0, T1, TMax};
+
+const FREQUENCY: u32 = 4; // Hz
+const CONFIRM: u32 = 5; // s
+
+// RESOURCES
+peripherals!(stm32f100, {
+    FLASH: Peripheral {
+        register_block: Flash,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C1,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    IWDG: Peripheral {
+        register_block: Iwdg,
+        ceiling: C1,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    SCB: Peripheral {
+        register_block: Scb,
+        ceiling: C1,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C1,
+    },
+});
+
+static mut CONFIRMED: bool = false;
+static mut TICKS: u32 = 0;
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+    Button(&gpioa).init(&rcc);
+
+    let timer = Timer(&tim7);
+    timer.init(&rcc, FREQUENCY);
+    timer.resume();
+}
+
+
+fn idle(ref priority: P0, ref threshold: T0) -> ! {
+    loop {
+        // NOTE the flash write stalls the CPU, so it's done here rather than
+        // in the task
+        if unsafe { TICKS >= CONFIRM * FREQUENCY && !CONFIRMED } {
+            let flash = FLASH.access(priority, threshold);
+
+            let flash = Flash(&flash);
+            flash.unlock();
+            let confirmed = threshold.raise(&SCB, |threshold| {
+                let scb = SCB.access(priority, threshold);
+                update::confirm(flash, &scb).is_ok()
+            });
+            flash.lock();
+
+            unsafe { CONFIRMED = confirmed }
+        }
+
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    periodic: Task {
+        interrupt: Tim7Irq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn periodic(_task: Tim7Irq, ref priority: P1, ref threshold: T1) {
+    static mut STATE: bool = false;
+
+    let gpioa = GPIOA.access(priority, threshold);
+    let iwdg = IWDG.access(priority, threshold);
+    let scb = SCB.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    if Timer(&tim7).clear_update_flag().is_ok() {
+        let confirmed = unsafe { CONFIRMED };
+
+        // simulate a broken image while the button is held
+        if confirmed || !Button(&gpioa).is_pressed() {
+            IndependentWatchdog(&iwdg).feed();
+        }
+
+        let led = match update::running(&scb) {
+            Some(Slot::B) => &LEDS[1],
+            _ => &LEDS[0],
+        };
+
+        unsafe {
+            TICKS = TICKS.saturating_add(1);
+            STATE = !STATE;
+
+            if confirmed && TICKS < (CONFIRM + 1) * FREQUENCY {
+                LEDS[0].on();
+                LEDS[1].on();
+            } else if STATE {
+                led.on();
+            } else {
+                LEDS[0].off();
+                LEDS[1].off();
+            }
+        }
+    } else {
+        // only reachable thru `rtfm::request(periodic)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
@@ -0,0 +1,113 @@
+//! Feeds the independent watchdog from a periodic task; hold the user button
+//! to stop feeding it and let it reset the device
+//!
+//! The blue LED lights up after a reset caused by the watchdog
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::button::Button;
+use dsc::led::{self, LEDS};
+use dsc::reset::{self, Cause};
+use dsc::stm32f100::interrupt::Tim7Irq;
+use dsc::stm32f100;
+use dsc::timer::Timer;
+use dsc::watchdog::{self, IndependentWatchdog};
+use rtfm::{P0, P1, T0, T1, TMax};
+
+const FREQUENCY: u32 = 4; // Hz
+const TIMEOUT: u32 = 1_000; // ms
+
+// RESOURCES
+peripherals!(stm32f100, {
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C1,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    IWDG: Peripheral {
+        register_block: Iwdg,
+        ceiling: C1,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C1,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let dbg = DBG.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let iwdg = IWDG.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+    Button(&gpioa).init(&rcc);
+
+    if reset::reset_cause(&rcc) == Cause::IndependentWatchdog {
+        LEDS[0].on();
+    }
+
+    // don't bite while stopped at a breakpoint
+    watchdog::freeze_on_debug(&dbg);
+    IndependentWatchdog(&iwdg).start(TIMEOUT).ok();
+
+    let timer = Timer(&tim7);
+    timer.init(&rcc, FREQUENCY);
+    timer.resume();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    periodic: Task {
+        interrupt: Tim7Irq,
+        priority: P1,
+        enabled: true,
+    },
+});
+
+fn periodic(_task: Tim7Irq, ref priority: P1, ref threshold: T1) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let iwdg = IWDG.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    if Timer(&tim7).clear_update_flag().is_ok() {
+        // simulate a hang while the button is held
+        if !Button(&gpioa).is_pressed() {
+            IndependentWatchdog(&iwdg).feed();
+        }
+    } else {
+        // only reachable thru `rtfm::request(periodic)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
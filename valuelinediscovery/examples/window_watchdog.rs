@@ -0,0 +1,165 @@
+//! Refreshes the window watchdog every 50 ms from a periodic task; hold the
+//! user button to stall that task
+//!
+//! The early wakeup interrupt records the stall in the backup registers just
+//! before the reset. The blue LED lights up after such a reset.
+
+#![feature(const_fn)]
+#![feature(used)]
+#![no_std]
+
+extern crate cortex_m_rt;
+#[macro_use]
+extern crate cortex_m_rtfm as rtfm;
+extern crate valuelinediscovery as dsc;
+
+use dsc::backup::Backup;
+use dsc::button::Button;
+use dsc::led::{self, LEDS};
+use dsc::reset::{self, Cause};
+use dsc::stm32f100::interrupt::{Tim7Irq, WwdgIrq};
+use dsc::stm32f100;
+use dsc::timer::Timer;
+use dsc::watchdog::{self, WindowWatchdog};
+use rtfm::{P0, P1, P2, T0, T1, T2, TMax};
+
+const FREQUENCY: u32 = 20; // Hz
+const TIMEOUT: u32 = 80_000; // us
+const WINDOW: u32 = 30_000; // us
+
+// Backup register holding the last stall
+const LAST_STALL: usize = 1;
+const PERIODIC: u16 = 0x57;
+
+// RESOURCES
+peripherals!(stm32f100, {
+    BKP: Peripheral {
+        register_block: Bkp,
+        ceiling: C2,
+    },
+    DBG: Peripheral {
+        register_block: Dbg,
+        ceiling: C0,
+    },
+    GPIOA: Peripheral {
+        register_block: Gpioa,
+        ceiling: C1,
+    },
+    GPIOC: Peripheral {
+        register_block: Gpioc,
+        ceiling: C0,
+    },
+    PWR: Peripheral {
+        register_block: Pwr,
+        ceiling: C0,
+    },
+    RCC: Peripheral {
+        register_block: Rcc,
+        ceiling: C0,
+    },
+    TIM7: Peripheral {
+        register_block: Tim7,
+        ceiling: C1,
+    },
+    WWDG: Peripheral {
+        register_block: Wwdg,
+        ceiling: C2,
+    },
+});
+
+
+// Initialisation
+fn init(ref priority: P0, threshold: &TMax) {
+    let bkp = BKP.access(priority, threshold);
+    let dbg = DBG.access(priority, threshold);
+    let gpioa = GPIOA.access(priority, threshold);
+    let gpioc = GPIOC.access(priority, threshold);
+    let pwr = PWR.access(priority, threshold);
+    let rcc = RCC.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+    let wwdg = WWDG.access(priority, threshold);
+
+    led::init(&gpioc, &rcc);
+    Button(&gpioa).init(&rcc);
+
+    let backup = Backup(&bkp);
+    backup.init(&pwr, &rcc);
+    if reset::reset_cause(&rcc) == Cause::WindowWatchdog &&
+        backup.read(LAST_STALL).ok() == Some(PERIODIC)
+    {
+        LEDS[0].on();
+    }
+    backup.write(LAST_STALL, 0).ok();
+
+    // don't bite while stopped at a breakpoint
+    watchdog::freeze_on_debug(&dbg);
+    let watchdog = WindowWatchdog(&wwdg);
+    watchdog.start(&rcc, TIMEOUT, WINDOW).ok();
+    watchdog.listen(stalled);
+
+    let timer = Timer(&tim7);
+    timer.init(&rcc, FREQUENCY);
+    timer.resume();
+}
+
+
+fn idle(_priority: P0, _threshold: T0) -> ! {
+    loop {
+        //rtfm::wfi(); // this freezes JTAG, so don't do it
+    }
+}
+
+
+fn stalled() {
+    LEDS[1].on();
+}
+
+
+// TASKS
+tasks!(stm32f100, {
+    periodic: Task {
+        interrupt: Tim7Irq,
+        priority: P1,
+        enabled: true,
+    },
+    early_wakeup: Task {
+        interrupt: WwdgIrq,
+        priority: P2,
+        enabled: true,
+    },
+});
+
+fn periodic(_task: Tim7Irq, ref priority: P1, ref threshold: T1) {
+    let gpioa = GPIOA.access(priority, threshold);
+    let tim7 = TIM7.access(priority, threshold);
+
+    if Timer(&tim7).clear_update_flag().is_ok() {
+        // simulate a runaway loop while the button is held
+        while Button(&gpioa).is_pressed() {}
+
+        threshold.raise(
+            &WWDG, |threshold| {
+                let wwdg = WWDG.access(priority, threshold);
+                WindowWatchdog(&wwdg).refresh().ok();
+            }
+        );
+    } else {
+        // only reachable thru `rtfm::request(periodic)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
+
+fn early_wakeup(_task: WwdgIrq, ref priority: P2, ref threshold: T2) {
+    let bkp = BKP.access(priority, threshold);
+    let wwdg = WWDG.access(priority, threshold);
+
+    if WindowWatchdog(&wwdg).handle_interrupt().is_ok() {
+        // the reset follows within one counter tick (2 ms here)
+        Backup(&bkp).write(LAST_STALL, PERIODIC).ok();
+    } else {
+        // only reachable thru `rtfm::request(early_wakeup)`
+        #[cfg(debug_assertions)]
+        unreachable!()
+    }
+}
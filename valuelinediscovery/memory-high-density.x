@@ -0,0 +1,16 @@
+/* Layout of high density parts (`--features high-density`), for the
+   smallest of them, the STM32F100RC: 256K of flash and 24K of RAM */
+MEMORY
+{
+  /* NOTE K = KiBi = 1024 bytes */
+  /* the last 2 pages (2 x 2K) are reserved for `eeprom` */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 252K
+  /* the first 256 bytes of RAM are kept across resets (see `src/fault.rs`) */
+  NOINIT : ORIGIN = 0x20000000, LENGTH = 256
+  RAM   : ORIGIN = 0x20000100, LENGTH = 24K - 256
+}
+
+/* This is where the call stack will be allocated. */
+/* The stack is of the full descending type. */
+/* NOTE Do NOT modify `_stack_start` unless you know what you are doing */
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
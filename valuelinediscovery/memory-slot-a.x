@@ -0,0 +1,15 @@
+/* Layout of applications started from slot A of the bootloader
+   (`--features slot-a`), see `src/update.rs` */
+MEMORY
+{
+  /* NOTE K = KiBi = 1024 bytes */
+  FLASH : ORIGIN = 0x08004000, LENGTH = 55K
+  /* the first 256 bytes of RAM are kept across resets (see `src/fault.rs`) */
+  NOINIT : ORIGIN = 0x20000000, LENGTH = 256
+  RAM   : ORIGIN = 0x20000100, LENGTH = 8K - 256
+}
+
+/* This is where the call stack will be allocated. */
+/* The stack is of the full descending type. */
+/* NOTE Do NOT modify `_stack_start` unless you know what you are doing */
+_stack_start = ORIGIN(RAM) + LENGTH(RAM);
@@ -1,8 +1,12 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
-  RAM   : ORIGIN = 0x20000000, LENGTH = 8K
+  /* the last 2 pages (2 x 1K) are reserved for `eeprom`; high density
+     parts have 2K pages, see `memory-high-density.x` */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 126K
+  /* the first 256 bytes of RAM are kept across resets (see `src/fault.rs`) */
+  NOINIT : ORIGIN = 0x20000000, LENGTH = 256
+  RAM   : ORIGIN = 0x20000100, LENGTH = 8K - 256
 }
 
 /* This is where the call stack will be allocated. */
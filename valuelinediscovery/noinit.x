@@ -0,0 +1,8 @@
+/* Data that survives resets: not loaded, not zeroed */
+SECTIONS
+{
+  .noinit (NOLOAD) :
+  {
+    *(.noinit .noinit.*);
+  } > NOINIT
+}
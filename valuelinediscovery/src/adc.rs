@@ -0,0 +1,838 @@
+//! Analog to Digital Converter (ADC1)
+//!
+//! Channel  Pin
+//! 0-7      PA0-PA7
+//! 8-9      PB0-PB1
+//! 10-15    PC0-PC5
+//! 16       Internal temperature sensor
+//! 17       Internal reference voltage (VREFINT)
+//!
+//! The ADC is clocked from PCLK2 / 2, which keeps it under the 12 MHz
+//! maximum.
+//!
+//! Scan mode results are moved into memory by DMA1 channel 1.
+
+use cast::{i32, i64, u16, u32, u64, usize};
+use cortex_m::asm;
+use stm32f100::{Adc1, Dma1, Rcc};
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// Highest channel number of ADC1
+pub const MAX_CHANNEL: u8 = 17;
+
+/// Channel of the internal temperature sensor
+pub const TEMPERATURE: u8 = 16;
+
+/// Channel of the internal reference voltage
+pub const VREFINT: u8 = 17;
+
+/// Full scale reading of a 12-bit conversion
+pub const FULL_SCALE: u32 = 4095;
+
+// Typical values from the STM32F100xB datasheet, table "Temperature sensor
+// characteristics" and "Embedded internal reference voltage"
+/// Temperature sensor voltage at 25 C, in mV
+pub const V25: u32 = 1_410;
+/// Temperature sensor slope, in uV per C
+pub const AVG_SLOPE: u32 = 4_300;
+/// Internal reference voltage, in mV
+pub const VREFINT_MV: u32 = 1_200;
+
+/// Sample time of a channel, in ADC clock cycles
+#[derive(Clone, Copy, PartialEq)]
+pub enum SampleTime {
+    /// 1.5 cycles
+    Cycles1_5,
+    /// 7.5 cycles
+    Cycles7_5,
+    /// 13.5 cycles
+    Cycles13_5,
+    /// 28.5 cycles
+    Cycles28_5,
+    /// 41.5 cycles
+    Cycles41_5,
+    /// 55.5 cycles
+    Cycles55_5,
+    /// 71.5 cycles
+    Cycles71_5,
+    /// 239.5 cycles
+    Cycles239_5,
+}
+
+impl SampleTime {
+    fn bits(self) -> u32 {
+        match self {
+            SampleTime::Cycles1_5 => 0b000,
+            SampleTime::Cycles7_5 => 0b001,
+            SampleTime::Cycles13_5 => 0b010,
+            SampleTime::Cycles28_5 => 0b011,
+            SampleTime::Cycles41_5 => 0b100,
+            SampleTime::Cycles55_5 => 0b101,
+            SampleTime::Cycles71_5 => 0b110,
+            SampleTime::Cycles239_5 => 0b111,
+        }
+    }
+}
+
+/// Conversion mode of the regular group
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Convert the first channel of the sequence once per trigger
+    Single,
+    /// Convert the first channel of the sequence over and over
+    Continuous,
+    /// Convert the whole sequence once per trigger
+    Scan,
+    /// Convert the whole sequence over and over
+    ContinuousScan,
+}
+
+/// Alignment of the conversion results
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    /// 12-bit result in bits 0-11
+    Right,
+    /// 12-bit result in bits 4-15
+    Left,
+}
+
+/// Start-of-conversion trigger for the regular group
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// TIM1 capture/compare 1
+    Tim1Cc1,
+    /// TIM1 capture/compare 2
+    Tim1Cc2,
+    /// TIM1 capture/compare 3
+    Tim1Cc3,
+    /// TIM2 capture/compare 2
+    Tim2Cc2,
+    /// TIM3 TRGO
+    Tim3Trgo,
+    /// TIM4 capture/compare 4
+    Tim4Cc4,
+    /// EXTI line 11
+    Exti11,
+    /// `Adc::start`
+    Software,
+}
+
+impl Trigger {
+    fn bits(self) -> u8 {
+        match self {
+            Trigger::Tim1Cc1 => 0b000,
+            Trigger::Tim1Cc2 => 0b001,
+            Trigger::Tim1Cc3 => 0b010,
+            Trigger::Tim2Cc2 => 0b011,
+            Trigger::Tim3Trgo => 0b100,
+            Trigger::Tim4Cc4 => 0b101,
+            Trigger::Exti11 => 0b110,
+            Trigger::Software => 0b111,
+        }
+    }
+}
+
+/// Start-of-conversion trigger for the injected group
+#[derive(Clone, Copy, PartialEq)]
+pub enum InjectedTrigger {
+    /// TIM1 TRGO
+    Tim1Trgo,
+    /// TIM1 capture/compare 4
+    Tim1Cc4,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM2 capture/compare 1
+    Tim2Cc1,
+    /// TIM3 capture/compare 4
+    Tim3Cc4,
+    /// TIM4 TRGO
+    Tim4Trgo,
+    /// EXTI line 15
+    Exti15,
+    /// `Adc::start_injected`
+    Software,
+}
+
+impl InjectedTrigger {
+    fn bits(self) -> u8 {
+        match self {
+            InjectedTrigger::Tim1Trgo => 0b000,
+            InjectedTrigger::Tim1Cc4 => 0b001,
+            InjectedTrigger::Tim2Trgo => 0b010,
+            InjectedTrigger::Tim2Cc1 => 0b011,
+            InjectedTrigger::Tim3Cc4 => 0b100,
+            InjectedTrigger::Tim4Trgo => 0b101,
+            InjectedTrigger::Exti15 => 0b110,
+            InjectedTrigger::Software => 0b111,
+        }
+    }
+}
+
+/// Channels guarded by the analog watchdog
+#[derive(Clone, Copy, PartialEq)]
+pub enum Watch {
+    /// A single channel, regular or injected
+    Channel(u8),
+    /// Every channel of the regular group
+    AllRegular,
+    /// Every channel of the injected group
+    AllInjected,
+    /// Every channel of both groups
+    All,
+}
+
+/// Called from `Adc::handle_interrupt` with the reading that left the
+/// analog watchdog window
+pub type WatchdogHandler = fn(u16);
+
+static mut WATCHDOG_HANDLER: Option<WatchdogHandler> = None;
+
+/// Analog to Digital Converter
+///
+/// # Interrupts
+///
+/// - `AdcIrq` - EOC (end of regular conversion), JEOC (end of injected
+///   conversion), AWD (analog watchdog)
+/// - `Dma1Channel1Irq` - transfer complete of the scan mode buffer
+#[derive(Clone, Copy)]
+pub struct Adc<'a>(pub &'a Adc1);
+
+impl<'a> Adc<'a> {
+    /// Initializes and calibrates the ADC
+    ///
+    /// After initialization the ADC is powered on, in `Mode::Single`, with
+    /// right aligned results, a software trigger, and every channel sampled
+    /// for `SampleTime::Cycles28_5`.
+    pub fn init(&self, rcc: &Rcc) {
+        let adc1 = self.0;
+
+        // Power up the peripheral, ADCCLK = PCLK2 / 2
+        rcc.apb2enr.modify(|_, w| w.adc1en().enabled());
+        rcc.cfgr.modify(|_, w| unsafe { w.adcpre().bits(0b00) });
+
+        adc1.cr1.write(|w| unsafe { w.bits(0) });
+        adc1.cr2.write(|w| unsafe { w.bits(0) });
+
+        for channel in 0..MAX_CHANNEL + 1 {
+            self.set_sample_time(channel, SampleTime::Cycles28_5)
+                .ok();
+        }
+
+        self.set_trigger(Trigger::Software);
+
+        // Wake up the ADC, then wait for it to stabilize (tSTAB, 1 us)
+        adc1.cr2.modify(|_, w| unsafe { w.adon().bits(1) });
+        for _ in 0..16 {
+            asm::nop();
+        }
+
+        self.calibrate();
+    }
+
+    /// Runs the self calibration
+    ///
+    /// NOTE The ADC must be powered on and idle
+    pub fn calibrate(&self) {
+        let adc1 = self.0;
+
+        adc1.cr2.modify(|_, w| unsafe { w.rstcal().bits(1) });
+        while adc1.cr2.read().rstcal().bits() == 1 {}
+
+        adc1.cr2.modify(|_, w| unsafe { w.cal().bits(1) });
+        while adc1.cr2.read().cal().bits() == 1 {}
+    }
+
+    /// Sets the sample time of `channel`
+    ///
+    /// Returns `Err` if `channel` is not a valid ADC1 channel
+    pub fn set_sample_time(&self, channel: u8, time: SampleTime) -> Result<()> {
+        let adc1 = self.0;
+
+        if channel > MAX_CHANNEL {
+            return Err(Error { _0: () });
+        }
+
+        if channel < 10 {
+            let offset = 3 * u32(channel);
+            adc1.smpr2.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b111 << offset) | time.bits() << offset)
+            });
+        } else {
+            let offset = 3 * u32(channel - 10);
+            adc1.smpr1.modify(|r, w| unsafe {
+                w.bits(r.bits() & !(0b111 << offset) | time.bits() << offset)
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Selects the conversion mode of the regular group
+    pub fn set_mode(&self, mode: Mode) {
+        let adc1 = self.0;
+
+        let (scan, cont) = match mode {
+            Mode::Single => (0, 0),
+            Mode::Continuous => (0, 1),
+            Mode::Scan => (1, 0),
+            Mode::ContinuousScan => (1, 1),
+        };
+
+        adc1.cr1.modify(|_, w| unsafe { w.scan().bits(scan) });
+        adc1.cr2.modify(|_, w| unsafe { w.cont().bits(cont) });
+    }
+
+    /// Selects the alignment of the conversion results
+    pub fn set_align(&self, align: Align) {
+        let bit = match align {
+            Align::Right => 0,
+            Align::Left => 1,
+        };
+
+        self.0.cr2.modify(|_, w| unsafe { w.align().bits(bit) });
+    }
+
+    /// Selects what starts a conversion of the regular group
+    pub fn set_trigger(&self, trigger: Trigger) {
+        self.0
+            .cr2
+            .modify(|_, w| unsafe {
+                w.extsel().bits(trigger.bits()).exttrig().bits(1)
+            });
+    }
+
+    /// Sets the channels of the regular group, in conversion order
+    ///
+    /// Returns `Err` if the sequence is empty, longer than 16 channels or
+    /// contains an invalid channel
+    pub fn set_sequence(&self, channels: &[u8]) -> Result<()> {
+        let adc1 = self.0;
+
+        if channels.is_empty() || channels.len() > 16 ||
+            channels.iter().any(|&c| c > MAX_CHANNEL)
+        {
+            return Err(Error { _0: () });
+        }
+
+        let (mut sqr1, mut sqr2, mut sqr3) = (0, 0, 0);
+        for (rank, &channel) in channels.iter().enumerate() {
+            let channel = u32(channel);
+            match rank {
+                0...5 => sqr3 |= channel << (5 * rank),
+                6...11 => sqr2 |= channel << (5 * (rank - 6)),
+                _ => sqr1 |= channel << (5 * (rank - 12)),
+            }
+        }
+        let l = (channels.len() as u32 - 1) << 20;
+
+        adc1.sqr3.write(|w| unsafe { w.bits(sqr3) });
+        adc1.sqr2.write(|w| unsafe { w.bits(sqr2) });
+        adc1.sqr1.write(|w| unsafe { w.bits(sqr1 | l) });
+
+        Ok(())
+    }
+
+    /// Starts a conversion of the regular group
+    ///
+    /// Only needed with `Trigger::Software`; hardware triggers start the
+    /// conversions on their own.
+    pub fn start(&self) {
+        self.0.cr2.modify(|_, w| unsafe { w.swstart().bits(1) });
+    }
+
+    /// Stops continuous conversions
+    ///
+    /// The conversion in progress is allowed to finish
+    pub fn stop(&self) {
+        self.0.cr2.modify(|_, w| unsafe { w.cont().bits(0) });
+    }
+
+    /// Reads the result of the last regular conversion
+    ///
+    /// Returns `Err` if no conversion has completed since the last read
+    pub fn read(&self) -> Result<u16> {
+        let adc1 = self.0;
+
+        if adc1.sr.read().eoc().bits() == 1 {
+            // NOTE reading DR clears EOC
+            Ok(adc1.dr.read().data().bits())
+        } else {
+            Err(Error { _0: () })
+        }
+    }
+
+    /// Converts a single `channel`, blocking until the result is ready
+    ///
+    /// NOTE This replaces the regular group sequence and leaves the ADC in
+    /// `Mode::Single` with a software trigger
+    pub fn convert(&self, channel: u8) -> Result<u16> {
+        self.set_mode(Mode::Single);
+        self.set_trigger(Trigger::Software);
+        self.set_sequence(&[channel])?;
+
+        self.start();
+        loop {
+            if let Ok(value) = self.read() {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Converts the regular group into `buffer` using DMA1 channel 1
+    ///
+    /// The regular sequence is set to `channels`. With `continuous` the ADC
+    /// scans over and over; otherwise a single scan is done per trigger.
+    /// Either way the DMA channel runs in circular mode, so `buffer` is
+    /// refreshed after every scan until `stop_scan` is called.
+    ///
+    /// Returns `Err` if `channels` is not a valid sequence or `buffer` is
+    /// shorter than `channels`
+    ///
+    /// # Interrupts
+    ///
+    /// - `Dma1Channel1Irq` - the whole sequence has been transferred
+    ///
+    /// # Safety
+    ///
+    /// The DMA keeps writing into `buffer` after this call returns. `buffer`
+    /// must stay alive until `stop_scan` is called and should only be read
+    /// (with volatile reads) after `clear_scan_flag` returns `Ok`.
+    pub unsafe fn scan(
+        &self,
+        dma1: &Dma1,
+        rcc: &Rcc,
+        channels: &[u8],
+        buffer: &mut [u16],
+        continuous: bool,
+    ) -> Result<()> {
+        let adc1 = self.0;
+
+        if buffer.len() < channels.len() {
+            return Err(Error { _0: () });
+        }
+        self.set_sequence(channels)?;
+
+        // Power up the DMA
+        rcc.ahbenr.modify(|_, w| w.dma1en().bits(1));
+
+        dma1.ccr1.write(|w| w.en().bits(0));
+        dma1.cpar1
+            .write(|w| w.bits(&adc1.dr as *const _ as u32));
+        dma1.cmar1
+            .write(|w| w.bits(buffer.as_mut_ptr() as u32));
+        dma1.cndtr1
+            .write(|w| w.ndt().bits(u16(channels.len()).unwrap()));
+        dma1.ifcr.write(|w| w.cgif1().bits(1));
+        dma1.ccr1
+            .write(|w| {
+                w.dir()         // peripheral to memory
+                    .bits(0)
+                    .psize()    // 16-bit
+                    .bits(0b01)
+                    .msize()    // 16-bit
+                    .bits(0b01)
+                    .minc()
+                    .bits(1)
+                    .circ()
+                    .bits(1)
+                    .tcie()
+                    .bits(1)
+                    .en()
+                    .bits(1)
+            });
+
+        self.set_mode(
+            if continuous {
+                Mode::ContinuousScan
+            } else {
+                Mode::Scan
+            },
+        );
+        adc1.cr2.modify(|_, w| w.dma().bits(1));
+
+        Ok(())
+    }
+
+    /// Stops scan conversions started with `scan`
+    pub fn stop_scan(&self, dma1: &Dma1) {
+        let adc1 = self.0;
+
+        adc1.cr2.modify(|_, w| unsafe { w.cont().bits(0).dma().bits(0) });
+        dma1.ccr1.modify(|_, w| unsafe { w.en().bits(0) });
+    }
+
+    /// Clears the DMA transfer complete flag of the scan buffer
+    ///
+    /// Returns `Err` if the scan hasn't completed
+    pub fn clear_scan_flag(&self, dma1: &Dma1) -> Result<()> {
+        if dma1.isr.read().tcif1().bits() == 0 {
+            Err(Error { _0: () })
+        } else {
+            dma1.ifcr.write(|w| unsafe { w.cgif1().bits(1) });
+            Ok(())
+        }
+    }
+
+    /// Enables the end of regular conversion interrupt
+    pub fn listen(&self) {
+        self.0.cr1.modify(|_, w| unsafe { w.eocie().bits(1) });
+    }
+
+    /// Disables the end of regular conversion interrupt
+    pub fn unlisten(&self) {
+        self.0.cr1.modify(|_, w| unsafe { w.eocie().bits(0) });
+    }
+
+    /// Sets the channels of the injected group, in conversion order, and
+    /// their data offsets
+    ///
+    /// The offset of each channel is subtracted from its raw result, so the
+    /// injected results are signed.
+    ///
+    /// Returns `Err` if the sequence is empty, longer than 4 channels or
+    /// contains an invalid channel
+    pub fn set_injected(&self, channels: &[(u8, u16)]) -> Result<()> {
+        let adc1 = self.0;
+
+        if channels.is_empty() || channels.len() > 4 ||
+            channels.iter().any(|&(c, _)| c > MAX_CHANNEL)
+        {
+            return Err(Error { _0: () });
+        }
+
+        // NOTE a sequence shorter than 4 channels occupies the *last*
+        // JSQx slots, but its results still start at JDR1
+        let first = 4 - channels.len();
+        let mut jsqr = (channels.len() as u32 - 1) << 20;
+        for (rank, &(channel, _)) in channels.iter().enumerate() {
+            jsqr |= u32(channel) << (5 * (first + rank));
+        }
+        adc1.jsqr.write(|w| unsafe { w.bits(jsqr) });
+
+        for (rank, &(_, offset)) in channels.iter().enumerate() {
+            match rank {
+                0 => adc1.jofr1.write(|w| unsafe { w.joffset1().bits(offset) }),
+                1 => adc1.jofr2.write(|w| unsafe { w.joffset2().bits(offset) }),
+                2 => adc1.jofr3.write(|w| unsafe { w.joffset3().bits(offset) }),
+                _ => adc1.jofr4.write(|w| unsafe { w.joffset4().bits(offset) }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Selects what starts a conversion of the injected group
+    pub fn set_injected_trigger(&self, trigger: InjectedTrigger) {
+        self.0
+            .cr2
+            .modify(|_, w| unsafe {
+                w.jextsel().bits(trigger.bits()).jexttrig().bits(1)
+            });
+    }
+
+    /// Starts a conversion of the injected group
+    ///
+    /// Only needed with `InjectedTrigger::Software`
+    pub fn start_injected(&self) {
+        self.0.cr2.modify(|_, w| unsafe { w.jswstart().bits(1) });
+    }
+
+    /// Enables the end of injected conversion interrupt
+    pub fn listen_injected(&self) {
+        self.0.cr1.modify(|_, w| unsafe { w.jeocie().bits(1) });
+    }
+
+    /// Disables the end of injected conversion interrupt
+    pub fn unlisten_injected(&self) {
+        self.0.cr1.modify(|_, w| unsafe { w.jeocie().bits(0) });
+    }
+
+    /// Clears the end of injected conversion flag
+    ///
+    /// Returns `Err` if the injected group hasn't been converted
+    pub fn clear_injected_flag(&self) -> Result<()> {
+        let adc1 = self.0;
+
+        if adc1.sr.read().jeoc().bits() == 0 {
+            Err(Error { _0: () })
+        } else {
+            adc1.sr
+                .modify(|_, w| unsafe { w.jeoc().bits(0).jstrt().bits(0) });
+            Ok(())
+        }
+    }
+
+    /// Reads the result of the injected conversion of `rank` (0-3)
+    ///
+    /// The data offset has already been subtracted, so the result may be
+    /// negative.
+    pub fn read_injected(&self, rank: u8) -> i16 {
+        let adc1 = self.0;
+
+        let bits = match rank {
+            0 => adc1.jdr1.read().jdata().bits(),
+            1 => adc1.jdr2.read().jdata().bits(),
+            2 => adc1.jdr3.read().jdata().bits(),
+            _ => adc1.jdr4.read().jdata().bits(),
+        };
+
+        bits as i16
+    }
+
+    /// Enables the temperature sensor and the internal reference voltage
+    ///
+    /// Both channels are sampled for `SampleTime::Cycles239_5` (60 us),
+    /// well above the 17.1 us the temperature sensor needs.
+    pub fn enable_internal(&self) {
+        self.0.cr2.modify(|_, w| unsafe { w.tsvrefe().bits(1) });
+
+        self.set_sample_time(TEMPERATURE, SampleTime::Cycles239_5).ok();
+        self.set_sample_time(VREFINT, SampleTime::Cycles239_5).ok();
+
+        // Sensor start-up time (tSTART, 10 us)
+        for _ in 0..100 {
+            asm::nop();
+        }
+    }
+
+    /// Disables the temperature sensor and the internal reference voltage
+    pub fn disable_internal(&self) {
+        self.0.cr2.modify(|_, w| unsafe { w.tsvrefe().bits(0) });
+    }
+
+    /// Measures the analog supply voltage VDDA, in mV
+    ///
+    /// NOTE `enable_internal` must have been called, and the results must be
+    /// right aligned. Like `convert`, this replaces the regular sequence.
+    pub fn vdda(&self) -> Result<u32> {
+        let raw = self.convert(VREFINT)?;
+
+        if raw == 0 {
+            Err(Error { _0: () })
+        } else {
+            Ok(vdda(raw))
+        }
+    }
+
+    /// Measures the die temperature, in milli degrees Celsius
+    ///
+    /// VDDA is measured first, so the result doesn't depend on the supply.
+    ///
+    /// NOTE `enable_internal` must have been called, and the results must be
+    /// right aligned. Like `convert`, this replaces the regular sequence.
+    pub fn temperature(&self) -> Result<i32> {
+        let vdda = self.vdda()?;
+        let raw = self.convert(TEMPERATURE)?;
+
+        Ok(temperature(raw, vdda))
+    }
+
+    /// Converts `channel` and returns the reading in mV
+    ///
+    /// The reading is corrected against the measured VDDA
+    ///
+    /// NOTE `enable_internal` must have been called, and the results must be
+    /// right aligned. Like `convert`, this replaces the regular sequence.
+    pub fn millivolts(&self, channel: u8) -> Result<u32> {
+        let vdda = self.vdda()?;
+        let raw = self.convert(channel)?;
+
+        Ok(millivolts(raw, vdda))
+    }
+
+    /// Arms the analog watchdog on `watch` with a window of raw readings
+    ///
+    /// A conversion outside `low..high` (inclusive) sets the AWD flag and,
+    /// if `listen_watchdog` was called, raises `AdcIrq`.
+    ///
+    /// NOTE The thresholds are compared against the 12-bit result, before
+    /// alignment and before the injected data offsets are subtracted.
+    ///
+    /// Returns `Err` if `low > high`, a threshold is above 12 bits or the
+    /// channel is invalid
+    pub fn watch(&self, watch: Watch, low: u16, high: u16) -> Result<()> {
+        let adc1 = self.0;
+
+        if low > high || u32(high) > FULL_SCALE {
+            return Err(Error { _0: () });
+        }
+
+        let (channel, single, regular, injected) = match watch {
+            Watch::Channel(channel) => {
+                if channel > MAX_CHANNEL {
+                    return Err(Error { _0: () });
+                }
+                (channel, 1, 1, 1)
+            }
+            Watch::AllRegular => (0, 0, 1, 0),
+            Watch::AllInjected => (0, 0, 0, 1),
+            Watch::All => (0, 0, 1, 1),
+        };
+
+        adc1.ltr.write(|w| unsafe { w.lt().bits(low) });
+        adc1.htr.write(|w| unsafe { w.ht().bits(high) });
+        adc1.cr1
+            .modify(
+                |_, w| unsafe {
+                    w.awdch()
+                        .bits(channel)
+                        .awdsgl()
+                        .bits(single)
+                        .awden()
+                        .bits(regular)
+                        .jawden()
+                        .bits(injected)
+                },
+            );
+
+        Ok(())
+    }
+
+    /// Arms the analog watchdog on `watch` with a window in mV, given the
+    /// VDDA in mV
+    pub fn watch_millivolts(
+        &self,
+        watch: Watch,
+        low: u32,
+        high: u32,
+        vdda: u32,
+    ) -> Result<()> {
+        if vdda == 0 {
+            return Err(Error { _0: () });
+        }
+
+        let low = u16(low * FULL_SCALE / vdda).map_err(|_| Error { _0: () })?;
+        let high = u16(::core::cmp::min(high * FULL_SCALE / vdda, FULL_SCALE))
+            .unwrap();
+
+        self.watch(watch, low, high)
+    }
+
+    /// Disarms the analog watchdog
+    pub fn unwatch(&self) {
+        self.0
+            .cr1
+            .modify(|_, w| unsafe { w.awden().bits(0).jawden().bits(0) });
+    }
+
+    /// Enables the analog watchdog interrupt, calling `handler` from
+    /// `handle_interrupt`
+    pub fn listen_watchdog(&self, handler: WatchdogHandler) {
+        // NOTE(unsafe) the interrupt is disabled until the handler is set
+        unsafe { WATCHDOG_HANDLER = Some(handler) }
+        self.0.cr1.modify(|_, w| unsafe { w.awdie().bits(1) });
+    }
+
+    /// Disables the analog watchdog interrupt
+    pub fn unlisten_watchdog(&self) {
+        self.0.cr1.modify(|_, w| unsafe { w.awdie().bits(0) });
+        unsafe { WATCHDOG_HANDLER = None }
+    }
+
+    /// Clears the analog watchdog flag
+    ///
+    /// Returns `Err` if the watchdog hasn't fired
+    pub fn clear_watchdog_flag(&self) -> Result<()> {
+        let adc1 = self.0;
+
+        if adc1.sr.read().awd().bits() == 0 {
+            Err(Error { _0: () })
+        } else {
+            adc1.sr.modify(|_, w| unsafe { w.awd().bits(0) });
+            Ok(())
+        }
+    }
+
+    /// Services the analog watchdog; call this from the `AdcIrq` task
+    ///
+    /// If the watchdog fired, the flag is cleared and the handler passed to
+    /// `listen_watchdog` is called with the reading that left the window:
+    /// the one of a watched injected channel if one is outside the window,
+    /// else the last regular reading.
+    ///
+    /// Returns `Err` if the watchdog hasn't fired
+    pub fn handle_interrupt(&self) -> Result<()> {
+        self.clear_watchdog_flag()?;
+
+        let value = self.watchdog_reading();
+        if let Some(handler) = unsafe { WATCHDOG_HANDLER } {
+            handler(value);
+        }
+
+        Ok(())
+    }
+
+    /// The reading that most likely set the AWD flag
+    ///
+    /// NOTE assumes right aligned results, like `watch`
+    fn watchdog_reading(&self) -> u16 {
+        let adc1 = self.0;
+
+        let cr1 = adc1.cr1.read();
+        let low = adc1.ltr.read().lt().bits();
+        let high = adc1.htr.read().ht().bits();
+
+        if cr1.jawden().bits() == 1 {
+            let jsqr = adc1.jsqr.read().bits();
+            let len = usize(adc1.jsqr.read().jl().bits()) + 1;
+            let first = 4 - len;
+
+            for rank in 0..len {
+                let channel = (jsqr >> (5 * (first + rank))) as u8 & 0x1F;
+                if cr1.awdsgl().bits() == 1 && channel != cr1.awdch().bits() {
+                    continue;
+                }
+
+                // NOTE the watchdog compares the result before the data
+                // offset is subtracted
+                let offset = match rank {
+                    0 => adc1.jofr1.read().joffset1().bits(),
+                    1 => adc1.jofr2.read().joffset2().bits(),
+                    2 => adc1.jofr3.read().joffset3().bits(),
+                    _ => adc1.jofr4.read().joffset4().bits(),
+                };
+                let raw = (self.read_injected(rank as u8) as u16)
+                    .wrapping_add(offset) & 0xFFF;
+
+                if raw < low || raw > high {
+                    return raw;
+                }
+            }
+        }
+
+        adc1.dr.read().data().bits()
+    }
+}
+
+/// Computes VDDA, in mV, from a raw reading of the internal reference
+pub fn vdda(vrefint: u16) -> u32 {
+    VREFINT_MV * FULL_SCALE / u32(vrefint)
+}
+
+/// Converts a raw reading into mV, given the VDDA in mV
+pub fn millivolts(raw: u16, vdda: u32) -> u32 {
+    u32(raw) * vdda / FULL_SCALE
+}
+
+/// Converts a raw reading of the temperature sensor into milli degrees
+/// Celsius, given the VDDA in mV
+pub fn temperature(raw: u16, vdda: u32) -> i32 {
+    // work in uV to keep the precision of the slope
+    // NOTE(u64) a bogus `vdda`, from a bad VREFINT reading, overflows u32
+    let vsense = (u64(raw) * u64(vdda) * 1_000 / u64(FULL_SCALE)) as i64;
+    let v25 = i64(V25) * 1_000;
+
+    let t = (v25 - vsense) * 10 / i64(AVG_SLOPE / 100) + 25_000;
+    i32(t).unwrap_or(if t < 0 {
+        i32::min_value()
+    } else {
+        i32::max_value()
+    })
+}
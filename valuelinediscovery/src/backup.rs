@@ -0,0 +1,257 @@
+//! Backup registers
+//!
+//! The backup data registers keep their contents through resets and
+//! standby for as long as VDD or VBAT is present. Medium density parts,
+//! like the STM32F100RB on the Discovery, have 10 16-bit registers; high
+//! density parts have 42 (enable the `high-density` feature).
+//!
+//! `Backup::store` uses all but one of them, for a `CAPACITY` of 18 bytes,
+//! or 82 on high density parts. The crash record of `reset`, with the
+//! "crash-record" feature, takes the last `RESERVED` registers: that leaves
+//! 6 bytes, or 70.
+//!
+//! - TAMPER - PC13
+//!
+//! NOTE the LCD uses PC13 as its enable line, so tamper detection can't be
+//! used together with `lcd`.
+
+use core::{mem, ptr};
+
+use cast::{u16, u32};
+use stm32f100::{Bkp, Pwr, Rcc};
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+#[derive(Clone, Copy, PartialEq)]
+pub enum Error {
+    /// Register index out of range
+    Index,
+    /// The value doesn't fit in the backup registers
+    Size,
+    /// The stored checksum doesn't match the stored data
+    Checksum,
+    /// No tamper event has occurred
+    NoTamper,
+}
+
+/// Number of backup data registers
+#[cfg(not(feature = "high-density"))]
+pub const REGISTERS: usize = 10;
+
+/// Number of backup data registers
+#[cfg(feature = "high-density")]
+pub const REGISTERS: usize = 42;
+
+/// Number of registers, at the end, reserved for the crash record of
+/// `reset`; `store`, `load` and `clear` leave them alone
+#[cfg(feature = "crash-record")]
+pub const RESERVED: usize = 6;
+
+/// Number of registers, at the end, reserved for the crash record of
+/// `reset`: none without the "crash-record" feature
+#[cfg(not(feature = "crash-record"))]
+pub const RESERVED: usize = 0;
+
+/// Size, in bytes, of the largest value `store` can hold
+///
+/// The first register is used for the checksum; the `RESERVED` ones aren't
+/// used
+pub const CAPACITY: usize = 2 * (REGISTERS - RESERVED - 1);
+
+/// Active level of the TAMPER pin
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    /// A high level on the pin is a tamper event
+    High,
+    /// A low level on the pin is a tamper event
+    Low,
+}
+
+/// Powers up the backup domain interface and lifts its write protection
+///
+/// This is needed before writing the backup registers or the RTC
+pub fn unlock(pwr: &Pwr, rcc: &Rcc) {
+    rcc.apb1enr
+        .modify(|_, w| w.pwren().enabled().bkpen().enabled());
+    pwr.cr.modify(|_, w| unsafe { w.dbp().bits(1) });
+}
+
+/// Write protects the backup domain again
+pub fn lock(pwr: &Pwr) {
+    pwr.cr.modify(|_, w| unsafe { w.dbp().bits(0) });
+}
+
+/// Backup registers
+///
+/// # Interrupts
+///
+/// - `TamperStampIrq` - tamper event
+#[derive(Clone, Copy)]
+pub struct Backup<'a>(pub &'a Bkp);
+
+impl<'a> Backup<'a> {
+    /// Initializes the backup registers for reading and writing
+    pub fn init(&self, pwr: &Pwr, rcc: &Rcc) {
+        unlock(pwr, rcc);
+    }
+
+    /// Reads the backup register `index` (0 = BKP_DR1)
+    pub fn read(&self, index: usize) -> Result<u16> {
+        let register = self.register(index)?;
+
+        // NOTE(read_volatile) the registers are 16 bits wide, the upper half
+        // of the word is reserved
+        Ok(unsafe { ptr::read_volatile(register as *const u16) })
+    }
+
+    /// Writes `value` into the backup register `index` (0 = BKP_DR1)
+    pub fn write(&self, index: usize, value: u16) -> Result<()> {
+        let register = self.register(index)?;
+
+        unsafe { ptr::write_volatile(register as *mut u32, u32(value)) }
+        Ok(())
+    }
+
+    /// Clears the backup registers, but the `RESERVED` ones
+    pub fn clear(&self) {
+        for index in 0..REGISTERS - RESERVED {
+            self.write(index, 0).ok();
+        }
+    }
+
+    /// Stores `value` in the backup registers, protected by a checksum
+    ///
+    /// `T` should be a `#[repr(C)]` struct made of plain integers
+    ///
+    /// Returns `Err` if `T` is bigger than `CAPACITY` bytes
+    pub fn store<T>(&self, value: &T) -> Result<()>
+    where
+        T: Copy,
+    {
+        let size = mem::size_of::<T>();
+        if size > CAPACITY {
+            return Err(Error::Size);
+        }
+
+        let mut buffer = [0u16; REGISTERS - RESERVED - 1];
+        unsafe {
+            ptr::copy_nonoverlapping(
+                value as *const T as *const u8,
+                buffer.as_mut_ptr() as *mut u8,
+                size,
+            );
+        }
+
+        let words = &buffer[..(size + 1) / 2];
+        for (i, word) in words.iter().enumerate() {
+            self.write(i + 1, *word)?;
+        }
+        self.write(0, checksum(words, size))
+    }
+
+    /// Loads a value stored with `store`
+    ///
+    /// Returns `Err` if `T` is bigger than `CAPACITY` bytes, or if the
+    /// checksum doesn't match (e.g. the registers were never written, VBAT
+    /// was lost or a tamper event wiped them)
+    pub fn load<T>(&self) -> Result<T>
+    where
+        T: Copy,
+    {
+        let size = mem::size_of::<T>();
+        if size > CAPACITY {
+            return Err(Error::Size);
+        }
+
+        let mut buffer = [0u16; REGISTERS - RESERVED - 1];
+        let len = (size + 1) / 2;
+        for i in 0..len {
+            buffer[i] = self.read(i + 1)?;
+        }
+
+        if self.read(0)? != checksum(&buffer[..len], size) {
+            return Err(Error::Checksum);
+        }
+
+        Ok(unsafe { ptr::read_unaligned(buffer.as_ptr() as *const T) })
+    }
+
+    /// Enables tamper detection on the TAMPER pin
+    ///
+    /// A tamper event clears all the backup registers
+    pub fn enable_tamper(&self, level: Level) {
+        let bkp = self.0;
+
+        let tpal = match level {
+            Level::High => 0,
+            Level::Low => 1,
+        };
+
+        // NOTE TPAL must be set before TPE, or a spurious event is detected
+        bkp.cr.modify(|_, w| unsafe { w.tpal().bits(tpal) });
+        bkp.cr.modify(|_, w| unsafe { w.tpe().bits(1) });
+    }
+
+    /// Disables tamper detection, freeing PC13 as a GPIO
+    pub fn disable_tamper(&self) {
+        self.0.cr.modify(|_, w| unsafe { w.tpe().bits(0) });
+    }
+
+    /// Enables the tamper interrupt
+    pub fn listen_tamper(&self) {
+        self.0.csr.modify(|_, w| unsafe { w.tpie().bits(1) });
+    }
+
+    /// Disables the tamper interrupt
+    pub fn unlisten_tamper(&self) {
+        self.0.csr.modify(|_, w| unsafe { w.tpie().bits(0) });
+    }
+
+    /// Clears the tamper event and interrupt flags
+    ///
+    /// The backup registers can't be written while the event flag is set
+    ///
+    /// Returns `Err` if no tamper event has occurred
+    pub fn clear_tamper_flag(&self) -> Result<()> {
+        let bkp = self.0;
+
+        if bkp.csr.read().tef().bits() == 0 {
+            Err(Error::NoTamper)
+        } else {
+            bkp.csr.modify(|_, w| unsafe { w.cte().bits(1).cti().bits(1) });
+            Ok(())
+        }
+    }
+
+    /// Address of the backup register `index`
+    fn register(&self, index: usize) -> Result<usize> {
+        let bkp = self.0;
+
+        if index >= REGISTERS {
+            Err(Error::Index)
+        } else if index < 10 {
+            Ok(&bkp.dr1 as *const _ as usize + 4 * index)
+        } else {
+            // NOTE DR11-DR42 live after the RTCCR, CR and CSR registers
+            Ok(&bkp.dr11 as *const _ as usize + 4 * (index - 10))
+        }
+    }
+}
+
+/// Fletcher-16 of `words`, seeded with the size of the stored value so a
+/// different type doesn't load as valid data
+fn checksum(words: &[u16], size: usize) -> u16 {
+    let mut sum1: u32 = u32(size);
+    let mut sum2: u32 = sum1;
+
+    for word in words {
+        for byte in &[*word as u8, (*word >> 8) as u8] {
+            sum1 = (sum1 + u32(*byte)) % 255;
+            sum2 = (sum2 + sum1) % 255;
+        }
+    }
+
+    u16(sum2 << 8 | sum1).unwrap()
+}
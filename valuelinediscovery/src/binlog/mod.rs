@@ -0,0 +1,246 @@
+//! Binary logging with deferred formatting
+//!
+//! `binlog!` sends a record as a handful of bytes: the index of its format
+//! string and its raw arguments. Nothing is formatted on the device, so
+//! it's fast enough for interrupt handlers like the encoder poller, and the
+//! format strings take no flash.
+//!
+//! ``` ignore
+//! binlog::init(Sink::Itm, Some(Level::Debug));
+//!
+//! binlog!(Level::Debug, "step {} to {}", direction, position);
+//! ```
+//!
+//! Each format string becomes the name of a one byte symbol in the
+//! `.binlog` section, which `binlog.x` places at address 0 and doesn't load:
+//! the address of the symbol is the index. `tools/stm32-binlog` reads the
+//! strings back from the ELF file and formats the records; it understands
+//! `{}`, `{:x}`, `{:X}`, `{:#x}` and `{:#X}`. See `wire` for the bytes.
+//!
+//! The arguments can be integers, `bool`, `f32` and `&str`. A record holds
+//! up to `wire::MAX_FRAME` bytes: arguments that don't fit are dropped, and
+//! the record is marked truncated.
+//!
+//! Records go over ITM, to stimulus port `PORT`, or the serial port. Don't
+//! share the serial port with text: the decoder would choke on it.
+//!
+//! NOTE a format string can be used by only one `binlog!`: it's a symbol
+//! name, and the linker rejects two symbols with the same name
+//!
+//! NOTE crates with the "custom-memory" feature must include `binlog.x` in
+//! their `memory.x`, else the indices are meaningless
+
+use cast::u16;
+use cortex_m::interrupt;
+use stm32f100::USART1;
+
+use itm::Port;
+use log::{Level, Sink};
+use serial::Serial;
+
+pub mod wire;
+
+/// ITM stimulus port of the records
+pub const PORT: u8 = 31;
+
+static mut SINK: Sink = Sink::None;
+static mut LEVEL: Option<Level> = Some(Level::Trace);
+
+/// Sends the records to `sink`, dropping the ones above `level`
+pub fn init(sink: Sink, level: Option<Level>) {
+    interrupt::free(|_| unsafe {
+        SINK = sink;
+        LEVEL = level;
+    })
+}
+
+/// Changes the level
+pub fn set_level(level: Option<Level>) {
+    interrupt::free(|_| unsafe { LEVEL = level })
+}
+
+/// Returns `true` if a record of `level` would be sent
+pub fn enabled(level: Level) -> bool {
+    let sink = unsafe { SINK };
+    sink != Sink::None && Some(level) <= unsafe { LEVEL }
+}
+
+/// A record being built; use `binlog!` instead
+#[doc(hidden)]
+pub struct Frame {
+    buffer: [u8; wire::MAX_FRAME],
+    len: usize,
+}
+
+impl Frame {
+    /// A record of `level` with the format string of `string`
+    pub fn new(level: Level, string: &'static u8) -> Frame {
+        // NOTE the address is in `.binlog`, which starts at 0
+        let index = u16(string as *const u8 as usize).unwrap_or(0xFFFF);
+
+        let mut frame = Frame {
+            buffer: [0; wire::MAX_FRAME],
+            len: 3,
+        };
+        frame.buffer[0] = level as u8;
+        frame.buffer[1] = index as u8;
+        frame.buffer[2] = (index >> 8) as u8;
+        frame
+    }
+
+    /// Appends an argument, or marks the record truncated if it doesn't fit
+    pub fn push(&mut self, tag: u8, value: &[u8]) {
+        if self.fits(1 + value.len()) {
+            self.append(&[tag]);
+            self.append(value);
+        }
+    }
+
+    /// Appends a string argument, or marks the record truncated if it
+    /// doesn't fit
+    pub fn push_str(&mut self, string: &str) {
+        let bytes = string.as_bytes();
+        if self.fits(2 + bytes.len()) {
+            self.append(&[wire::STR, bytes.len() as u8]);
+            self.append(bytes);
+        }
+    }
+
+    /// Returns `true` if `len` more bytes fit; else marks the record
+    /// truncated, so no later argument gets in either
+    fn fits(&mut self, len: usize) -> bool {
+        if self.buffer[0] & wire::TRUNCATED == 0 &&
+            self.len + len <= wire::MAX_FRAME
+        {
+            true
+        } else {
+            self.buffer[0] |= wire::TRUNCATED;
+            false
+        }
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    /// COBS encodes the record and sends it
+    pub fn send(&self) {
+        interrupt::free(|_| {
+            let sink = unsafe { SINK };
+
+            // NOTE(MAX_FRAME < 254) no block needs to be split
+            for block in self.buffer[..self.len].split(|byte| *byte == 0) {
+                write(sink, &[block.len() as u8 + 1]);
+                write(sink, block);
+            }
+            write(sink, &[0]);
+        })
+    }
+}
+
+/// A value `binlog!` can send
+pub trait Arg {
+    /// Appends the value to `frame`
+    fn encode(&self, frame: &mut Frame);
+}
+
+macro_rules! arg {
+    ($($ty:ty => $tag:ident, $size:expr;)+) => {
+        $(
+            impl Arg for $ty {
+                fn encode(&self, frame: &mut Frame) {
+                    let mut value = [0; $size];
+                    for (i, byte) in value.iter_mut().enumerate() {
+                        *byte = (*self >> (8 * i)) as u8;
+                    }
+                    frame.push(wire::$tag, &value);
+                }
+            }
+        )+
+    }
+}
+
+arg! {
+    u8 => U8, 1;
+    u16 => U16, 2;
+    u32 => U32, 4;
+    u64 => U64, 8;
+    i8 => I8, 1;
+    i16 => I16, 2;
+    i32 => I32, 4;
+    i64 => I64, 8;
+}
+
+impl Arg for usize {
+    fn encode(&self, frame: &mut Frame) {
+        (*self as u32).encode(frame)
+    }
+}
+
+impl Arg for isize {
+    fn encode(&self, frame: &mut Frame) {
+        (*self as i32).encode(frame)
+    }
+}
+
+impl Arg for bool {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push(wire::BOOL, &[*self as u8])
+    }
+}
+
+impl Arg for f32 {
+    fn encode(&self, frame: &mut Frame) {
+        let bits = self.to_bits();
+        let value = [
+            bits as u8,
+            (bits >> 8) as u8,
+            (bits >> 16) as u8,
+            (bits >> 24) as u8,
+        ];
+        frame.push(wire::F32, &value)
+    }
+}
+
+impl<'a> Arg for &'a str {
+    fn encode(&self, frame: &mut Frame) {
+        frame.push_str(self)
+    }
+}
+
+fn write(sink: Sink, bytes: &[u8]) {
+    match sink {
+        Sink::None => {}
+        Sink::Itm => Port(PORT).write_all(bytes),
+        Sink::Serial => {
+            // NOTE(unsafe) only writes to the TX buffer, in a critical
+            // section
+            let serial = Serial(unsafe { &*USART1.get() });
+            for byte in bytes {
+                while serial.write(*byte).is_err() {}
+            }
+        }
+    }
+}
+
+/// Sends a record of `level`, a `log::Level`, with deferred formatting
+///
+/// The format string must be a literal.
+#[macro_export]
+macro_rules! binlog {
+    ($level:expr, $string:expr $(, $arg:expr)*) => {{
+        let level = $level;
+        if Some(level) <= $crate::log::STATIC_MAX_LEVEL &&
+            $crate::binlog::enabled(level)
+        {
+            #[export_name = $string]
+            #[link_section = ".binlog"]
+            static STRING: u8 = 0;
+
+            let mut frame = $crate::binlog::Frame::new(level, &STRING);
+            $($crate::binlog::Arg::encode(&$arg, &mut frame);)*
+            frame.send();
+        }
+    }}
+}
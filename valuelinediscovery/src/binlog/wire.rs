@@ -0,0 +1,42 @@
+//! Wire format of the `binlog` records, shared with `tools/stm32-binlog`
+//!
+//! A record is COBS encoded, so it has no zero bytes, and followed by a
+//! zero byte. Decoded, it's
+//!
+//! - a header byte: the `log::Level` in bits 0-2, `TRUNCATED` in bit 7
+//! - the index of the format string, u16 little endian
+//! - the arguments, each a tag byte followed by the value, little endian.
+//!   A `STR` value is a length byte followed by the UTF-8 bytes.
+
+/// Longest record, before COBS encoding
+pub const MAX_FRAME: usize = 64;
+
+/// Header: arguments were dropped, the record didn't fit in `MAX_FRAME`
+pub const TRUNCATED: u8 = 1 << 7;
+
+/// Header: the level
+pub const LEVEL_MASK: u8 = 0b111;
+
+/// Tags of the arguments
+pub const U8: u8 = 1;
+pub const U16: u8 = 2;
+pub const U32: u8 = 3;
+pub const U64: u8 = 4;
+pub const I8: u8 = 5;
+pub const I16: u8 = 6;
+pub const I32: u8 = 7;
+pub const I64: u8 = 8;
+pub const BOOL: u8 = 9;
+pub const F32: u8 = 10;
+pub const STR: u8 = 11;
+
+/// Size of the value after `tag`; `None` for `STR` and unknown tags
+pub fn size(tag: u8) -> Option<usize> {
+    match tag {
+        U8 | I8 | BOOL => Some(1),
+        U16 | I16 => Some(2),
+        U32 | I32 | F32 => Some(4),
+        U64 | I64 => Some(8),
+        _ => None,
+    }
+}
@@ -0,0 +1,160 @@
+//! CRC calculation unit
+//!
+//! The CRC unit computes a CRC-32 with the polynomial 0x04C11DB7 over 32-bit
+//! words, most significant bit first, starting from 0xFFFFFFFF and without a
+//! final inversion. That's not the standard CRC-32 that zlib and the host
+//! tools compute, which works on bytes, least significant bit first, and
+//! inverts the result; `Crc::standard` and the conversions in `software`
+//! bridge the two.
+//!
+//! The unit holds a single computation: `reset` starts one, and `feed` (or
+//! `feed_dma`) can be called as many times as needed to continue it.
+
+use cast::{u16, u32};
+use stm32f100::{self, Dma1, Rcc};
+
+pub mod software;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// CRC calculation unit
+#[derive(Clone, Copy)]
+pub struct Crc<'a>(pub &'a stm32f100::Crc);
+
+impl<'a> Crc<'a> {
+    /// Powers up the CRC unit and starts a computation
+    pub fn init(&self, rcc: &Rcc) {
+        rcc.ahbenr.modify(|_, w| unsafe { w.crcen().bits(1) });
+
+        self.reset();
+    }
+
+    /// Starts a new computation
+    pub fn reset(&self) {
+        self.0.cr.write(|w| unsafe { w.reset().bits(1) });
+    }
+
+    /// Feeds `words` to the ongoing computation and returns the CRC so far
+    pub fn feed(&self, words: &[u32]) -> u32 {
+        let crc = self.0;
+
+        for word in words {
+            crc.dr.write(|w| unsafe { w.dr().bits(*word) });
+        }
+
+        self.result()
+    }
+
+    /// Returns the CRC of everything fed since the last `reset`
+    pub fn result(&self) -> u32 {
+        self.0.dr.read().dr().bits()
+    }
+
+    /// Returns the CRC of `words`
+    ///
+    /// NOTE this resets the ongoing computation
+    pub fn checksum(&self, words: &[u32]) -> u32 {
+        self.reset();
+        self.feed(words)
+    }
+
+    /// Returns the standard CRC-32 (zlib, Ethernet, PNG) of `bytes`
+    ///
+    /// The whole words go through the CRC unit; the last 1 - 3 bytes, if
+    /// any, are done in software.
+    ///
+    /// NOTE this resets the ongoing computation
+    pub fn standard(&self, bytes: &[u8]) -> u32 {
+        let crc = self.0;
+
+        self.reset();
+        let tail = bytes.len() - bytes.len() % 4;
+        for chunk in bytes[..tail].chunks(4) {
+            let word = u32(chunk[0]) | u32(chunk[1]) << 8 |
+                u32(chunk[2]) << 16 | u32(chunk[3]) << 24;
+            crc.dr.write(|w| unsafe { w.dr().bits(software::to_unit(word)) });
+        }
+
+        let register = software::reflect(self.result());
+        !bytes[tail..].iter().fold(register, |register, byte| {
+            software::update_standard(register, *byte)
+        })
+    }
+
+    /// Feeds `words` to the ongoing computation using DMA1 channel 2, in
+    /// memory to memory mode
+    ///
+    /// The CPU is free while the DMA moves the words. `clear_dma_flag`
+    /// returns `Ok` once it's done; `result` then returns the CRC.
+    ///
+    /// Returns `Err` if `words` is empty or longer than 65535 words
+    ///
+    /// # Interrupts
+    ///
+    /// - `Dma1Channel2Irq` - all the words have been fed
+    ///
+    /// # Safety
+    ///
+    /// The DMA keeps reading `words` after this call returns. `words` must
+    /// stay alive, and nothing else may be fed, until `clear_dma_flag`
+    /// returns `Ok`.
+    pub unsafe fn feed_dma(
+        &self,
+        dma1: &Dma1,
+        rcc: &Rcc,
+        words: &[u32],
+    ) -> Result<()> {
+        let crc = self.0;
+
+        let ndt = match u16(words.len()) {
+            Ok(ndt) if ndt > 0 => ndt,
+            _ => return Err(Error { _0: () }),
+        };
+
+        // Power up the DMA
+        rcc.ahbenr.modify(|_, w| w.dma1en().bits(1));
+
+        dma1.ccr2.write(|w| w.en().bits(0));
+        dma1.cpar2.write(|w| w.bits(&crc.dr as *const _ as u32));
+        dma1.cmar2.write(|w| w.bits(words.as_ptr() as u32));
+        dma1.cndtr2.write(|w| w.ndt().bits(ndt));
+        dma1.ifcr.write(|w| w.cgif2().bits(1));
+        dma1.ccr2.write(|w| {
+            w.mem2mem()
+                .bits(1)
+                .dir()          // memory to "peripheral"
+                .bits(1)
+                .psize()        // 32-bit
+                .bits(0b10)
+                .msize()        // 32-bit
+                .bits(0b10)
+                .minc()
+                .bits(1)
+                .tcie()
+                .bits(1)
+                .en()
+                .bits(1)
+        });
+
+        Ok(())
+    }
+
+    /// Clears the DMA transfer complete flag and releases the DMA channel
+    ///
+    /// Returns `Err` if the DMA hasn't fed all the words yet
+    pub fn clear_dma_flag(&self, dma1: &Dma1) -> Result<()> {
+        if dma1.isr.read().tcif2().bits() == 0 {
+            Err(Error { _0: () })
+        } else {
+            dma1.ifcr.write(|w| unsafe { w.cgif2().bits(1) });
+            dma1.ccr2.modify(|_, w| unsafe { w.en().bits(0) });
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! CRC-32 in software
+//!
+//! The same computation as the CRC unit, plus the standard CRC-32, and the
+//! conversions between them. Nothing here touches the hardware, so this file
+//! also builds for the host: `../tools/stm32-crc` uses it to make test
+//! vectors.
+
+/// Generator polynomial of the CRC unit (and of the standard CRC-32)
+pub const POLYNOMIAL: u32 = 0x04C1_1DB7;
+
+/// Value of the CRC unit after a reset (and initial value of the standard
+/// CRC-32)
+pub const INIT: u32 = 0xFFFF_FFFF;
+
+// `POLYNOMIAL`, bit reversed
+const POLYNOMIAL_REFLECTED: u32 = 0xEDB8_8320;
+
+/// Feeds `word` to a CRC unit computation that's at `crc`
+///
+/// The word is processed most significant bit first.
+pub fn update(crc: u32, word: u32) -> u32 {
+    let mut crc = crc ^ word;
+
+    for _ in 0..32 {
+        crc = if crc & 0x8000_0000 != 0 {
+            crc << 1 ^ POLYNOMIAL
+        } else {
+            crc << 1
+        };
+    }
+
+    crc
+}
+
+/// What the CRC unit computes for `words`, starting from a reset
+pub fn checksum(words: &[u32]) -> u32 {
+    words.iter().fold(INIT, |crc, word| update(crc, *word))
+}
+
+/// Feeds `byte` to a standard CRC-32 computation that's at `crc`
+///
+/// `crc` is the reflected shift register: without the final inversion.
+pub fn update_standard(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ u32::from(byte);
+
+    for _ in 0..8 {
+        crc = if crc & 1 != 0 {
+            crc >> 1 ^ POLYNOMIAL_REFLECTED
+        } else {
+            crc >> 1
+        };
+    }
+
+    crc
+}
+
+/// Standard CRC-32 (zlib, Ethernet, PNG) of `bytes`
+pub fn standard(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(INIT, |crc, byte| update_standard(crc, *byte))
+}
+
+/// Word to feed the CRC unit so it processes the 4 bytes of `word`, little
+/// endian, the way the standard CRC-32 does
+pub fn to_unit(word: u32) -> u32 {
+    reflect(word)
+}
+
+/// Standard CRC-32 of the bytes that were fed to the CRC unit through
+/// `to_unit`, given the result `crc` of the CRC unit
+pub fn to_standard(crc: u32) -> u32 {
+    !reflect(crc)
+}
+
+/// Result the CRC unit gives, for bytes fed through `to_unit`, when their
+/// standard CRC-32 is `crc`
+pub fn from_standard(crc: u32) -> u32 {
+    reflect(!crc)
+}
+
+/// Reverses the order of the bits of `word`
+pub fn reflect(word: u32) -> u32 {
+    let mut word = word;
+
+    word = (word & 0x5555_5555) << 1 | (word >> 1) & 0x5555_5555;
+    word = (word & 0x3333_3333) << 2 | (word >> 2) & 0x3333_3333;
+    word = (word & 0x0F0F_0F0F) << 4 | (word >> 4) & 0x0F0F_0F0F;
+    word.swap_bytes()
+}
+
+// NOTE run with `cargo test` in `../tools/stm32-crc`
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_known_answers() {
+        // CRC-32/MPEG-2 of the words, big endian
+        assert_eq!(checksum(&[]), INIT);
+        assert_eq!(checksum(&[0x0000_0000]), 0xC704_DD7B);
+        assert_eq!(checksum(&[0xFFFF_FFFF]), 0x0000_0000);
+        assert_eq!(checksum(&[0x1234_5678]), 0xDF8A_8A2B);
+        assert_eq!(checksum(&[0x1234_5678, 0x9ABC_DEF0]), 0x7D24_A31B);
+    }
+
+    #[test]
+    fn standard_known_answers() {
+        assert_eq!(standard(b""), 0x0000_0000);
+        assert_eq!(standard(b"123456789"), 0xCBF4_3926);
+        assert_eq!(standard(b"12345678"), 0x9AE0_DAAF);
+    }
+
+    #[test]
+    fn unit_matches_standard() {
+        // "12345678" as little endian words
+        let words = [0x3433_3231, 0x3837_3635];
+        let crc = words
+            .iter()
+            .fold(INIT, |crc, word| update(crc, to_unit(*word)));
+
+        assert_eq!(crc, 0x0AA4_F8A6);
+        assert_eq!(to_standard(crc), standard(b"12345678"));
+        assert_eq!(from_standard(standard(b"12345678")), crc);
+    }
+
+    #[test]
+    fn reflect_reverses_bits() {
+        assert_eq!(reflect(0x0000_0001), 0x8000_0000);
+        assert_eq!(reflect(0x04C1_1DB7), POLYNOMIAL_REFLECTED);
+        assert_eq!(reflect(reflect(0x1234_5678)), 0x1234_5678);
+    }
+}
@@ -0,0 +1,458 @@
+//! Digital to Analog Converter
+//!
+//! - Channel 1 - PA4
+//! - Channel 2 - PA5
+//!
+//! Waveforms are fed from memory by DMA1 channel 3 (channel 1) and DMA1
+//! channel 4 (channel 2), one sample per trigger.
+
+use cast::{u16, u32};
+use stm32f100::{self, Afio, Dma1, Gpioa, Rcc};
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// One period of a 12-bit sine wave
+pub static SINE: [u16; 64] = [
+    2048, 2249, 2447, 2642, 2831, 3013, 3185, 3347,
+    3495, 3630, 3750, 3853, 3939, 4007, 4056, 4085,
+    4095, 4085, 4056, 4007, 3939, 3853, 3750, 3630,
+    3495, 3347, 3185, 3013, 2831, 2642, 2447, 2249,
+    2048, 1847, 1649, 1454, 1265, 1083, 911, 749,
+    601, 466, 346, 243, 157, 89, 40, 11,
+    1, 11, 40, 89, 157, 243, 346, 466,
+    601, 749, 911, 1083, 1265, 1454, 1649, 1847,
+];
+
+/// DAC channel
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channel {
+    /// Channel 1, PA4
+    One,
+    /// Channel 2, PA5
+    Two,
+}
+
+/// Format of the data written to the DAC
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    /// 8-bit data in bits 0-7
+    Right8,
+    /// 12-bit data in bits 0-11
+    Right12,
+    /// 12-bit data in bits 4-15
+    Left12,
+}
+
+/// What moves the data holding register into the output
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// TIM6 TRGO
+    Tim6Trgo,
+    /// TIM3 TRGO
+    Tim3Trgo,
+    /// TIM7 TRGO
+    Tim7Trgo,
+    /// TIM15 TRGO (TIM5 TRGO on high density parts, see RM0041)
+    Tim15Trgo,
+    /// TIM2 TRGO
+    Tim2Trgo,
+    /// TIM4 TRGO
+    Tim4Trgo,
+    /// EXTI line 9
+    Exti9,
+    /// `Dac::trigger`
+    Software,
+}
+
+impl Trigger {
+    fn bits(self) -> u8 {
+        match self {
+            Trigger::Tim6Trgo => 0b000,
+            Trigger::Tim3Trgo => 0b001,
+            Trigger::Tim7Trgo => 0b010,
+            Trigger::Tim15Trgo => 0b011,
+            Trigger::Tim2Trgo => 0b100,
+            Trigger::Tim4Trgo => 0b101,
+            Trigger::Exti9 => 0b110,
+            Trigger::Software => 0b111,
+        }
+    }
+}
+
+/// Built-in wave generator
+///
+/// The generators add to the data holding register on every trigger, so
+/// they need a trigger to be selected.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wave {
+    /// No wave generation
+    Disabled,
+    /// LFSR noise, unmasking the `n` LSBs (1-12)
+    Noise(u8),
+    /// Triangle with an amplitude of `2^n - 1` (n = 1-12)
+    Triangle(u8),
+}
+
+/// Digital to Analog Converter
+///
+/// # Interrupts
+///
+/// - `Tim6DacIrq` - DMA underrun
+#[derive(Clone, Copy)]
+pub struct Dac<'a>(pub &'a stm32f100::Dac);
+
+impl<'a> Dac<'a> {
+    /// Initializes the DAC and configures PA4 and PA5 as analog pins
+    ///
+    /// NOTE After initialization, both channels are disabled
+    pub fn init(&self, gpioa: &Gpioa, rcc: &Rcc) {
+        let dac = self.0;
+
+        // Power up the peripherals
+        rcc.apb1enr.modify(|_, w| w.dacen().enabled());
+        rcc.apb2enr.modify(|_, w| w.iopaen().enabled());
+
+        // PA4 and PA5 as analog inputs, to avoid parasitic consumption
+        gpioa
+            .crl
+            .modify(
+                |_, w| {
+                    w.mode4().input().cnf4().push_pull() // really, analog
+                        .mode5().input().cnf5().push_pull()
+                },
+            );
+
+        dac.cr.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Enables the output of `channel`
+    ///
+    /// The output buffer lowers the output impedance but can't drive all the
+    /// way to the rails
+    pub fn enable(&self, channel: Channel, buffered: bool) {
+        let boff = if buffered { 0 } else { 1 };
+
+        match channel {
+            Channel::One => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.boff1().bits(boff).en1().bits(1) })
+            }
+            Channel::Two => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.boff2().bits(boff).en2().bits(1) })
+            }
+        }
+    }
+
+    /// Disables the output of `channel`
+    pub fn disable(&self, channel: Channel) {
+        match channel {
+            Channel::One => self.0.cr.modify(|_, w| unsafe { w.en1().bits(0) }),
+            Channel::Two => self.0.cr.modify(|_, w| unsafe { w.en2().bits(0) }),
+        }
+    }
+
+    /// Selects the trigger of `channel`
+    ///
+    /// With `None` the output follows every write
+    pub fn set_trigger(&self, channel: Channel, trigger: Option<Trigger>) {
+        let (ten, tsel) = match trigger {
+            Some(trigger) => (1, trigger.bits()),
+            None => (0, 0),
+        };
+
+        match channel {
+            Channel::One => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.tsel1().bits(tsel).ten1().bits(ten) })
+            }
+            Channel::Two => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.tsel2().bits(tsel).ten2().bits(ten) })
+            }
+        }
+    }
+
+    /// Selects the built-in wave generator of `channel`
+    ///
+    /// Returns `Err` if the size of the wave is not in the 1-12 range
+    pub fn set_wave(&self, channel: Channel, wave: Wave) -> Result<()> {
+        let (wave, mamp) = match wave {
+            Wave::Disabled => (0b00, 0),
+            Wave::Noise(n) | Wave::Triangle(n) if n == 0 || n > 12 => {
+                return Err(Error { _0: () })
+            }
+            Wave::Noise(n) => (0b01, n - 1),
+            Wave::Triangle(n) => (0b10, n - 1),
+        };
+
+        match channel {
+            Channel::One => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.wave1().bits(wave).mamp1().bits(mamp) })
+            }
+            Channel::Two => {
+                self.0
+                    .cr
+                    .modify(|_, w| unsafe { w.wave2().bits(wave).mamp2().bits(mamp) })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` into the data holding register of `channel`
+    pub fn write(&self, channel: Channel, format: Format, value: u16) {
+        let dac = self.0;
+
+        match (channel, format) {
+            (Channel::One, Format::Right8) => {
+                dac.dhr8r1.write(|w| unsafe { w.dacc1dhr().bits(value as u8) })
+            }
+            (Channel::One, Format::Right12) => {
+                dac.dhr12r1.write(|w| unsafe { w.dacc1dhr().bits(value) })
+            }
+            (Channel::One, Format::Left12) => {
+                dac.dhr12l1
+                    .write(|w| unsafe { w.dacc1dhr().bits(value >> 4) })
+            }
+            (Channel::Two, Format::Right8) => {
+                dac.dhr8r2.write(|w| unsafe { w.dacc2dhr().bits(value as u8) })
+            }
+            (Channel::Two, Format::Right12) => {
+                dac.dhr12r2.write(|w| unsafe { w.dacc2dhr().bits(value) })
+            }
+            (Channel::Two, Format::Left12) => {
+                dac.dhr12l2
+                    .write(|w| unsafe { w.dacc2dhr().bits(value >> 4) })
+            }
+        }
+    }
+
+    /// Writes both channels with a single register access, so they update
+    /// on the same trigger
+    pub fn write_dual(&self, format: Format, one: u16, two: u16) {
+        let dac = self.0;
+
+        match format {
+            Format::Right8 => {
+                dac.dhr8rd.write(|w| unsafe {
+                    w.dacc1dhr().bits(one as u8).dacc2dhr().bits(two as u8)
+                })
+            }
+            Format::Right12 => {
+                dac.dhr12rd.write(|w| unsafe {
+                    w.dacc1dhr().bits(one).dacc2dhr().bits(two)
+                })
+            }
+            Format::Left12 => {
+                dac.dhr12ld.write(|w| unsafe {
+                    w.dacc1dhr().bits(one >> 4).dacc2dhr().bits(two >> 4)
+                })
+            }
+        }
+    }
+
+    /// Software trigger of `channel`
+    ///
+    /// Only has an effect with `Trigger::Software`
+    pub fn trigger(&self, channel: Channel) {
+        match channel {
+            Channel::One => {
+                self.0.swtrigr.write(|w| unsafe { w.swtrig1().bits(1) })
+            }
+            Channel::Two => {
+                self.0.swtrigr.write(|w| unsafe { w.swtrig2().bits(1) })
+            }
+        }
+    }
+
+    /// Reads the value currently driven on `channel`, right aligned
+    pub fn output(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::One => self.0.dor1.read().dacc1dor().bits(),
+            Channel::Two => self.0.dor2.read().dacc2dor().bits(),
+        }
+    }
+
+    /// Plays `samples` in a loop on `channel`, one sample per `trigger`
+    ///
+    /// The samples are moved by DMA (channel 3 or 4 of DMA1), so no CPU time
+    /// is needed once this returns. Use e.g. `Timer6` with
+    /// `trigger_on_update` and a frequency of `sample_rate(..)` to set the
+    /// output frequency.
+    ///
+    /// Returns `Err` if `samples` is empty or longer than 65535 samples
+    pub fn play(
+        &self,
+        afio: &Afio,
+        dma1: &Dma1,
+        rcc: &Rcc,
+        channel: Channel,
+        format: Format,
+        trigger: Trigger,
+        samples: &'static [u16],
+    ) -> Result<()> {
+        let dac = self.0;
+
+        let ndt = u16(samples.len()).map_err(|_| Error { _0: () })?;
+        if ndt == 0 {
+            return Err(Error { _0: () });
+        }
+
+        let par = match (channel, format) {
+            (Channel::One, Format::Right8) => &dac.dhr8r1 as *const _ as u32,
+            (Channel::One, Format::Right12) => &dac.dhr12r1 as *const _ as u32,
+            (Channel::One, Format::Left12) => &dac.dhr12l1 as *const _ as u32,
+            (Channel::Two, Format::Right8) => &dac.dhr8r2 as *const _ as u32,
+            (Channel::Two, Format::Right12) => &dac.dhr12r2 as *const _ as u32,
+            (Channel::Two, Format::Left12) => &dac.dhr12l2 as *const _ as u32,
+        };
+        let mar = samples.as_ptr() as u32;
+
+        // Power up the DMA, route the DAC requests to DMA1
+        rcc.ahbenr.modify(|_, w| unsafe { w.dma1en().bits(1) });
+        rcc.apb2enr.modify(|_, w| w.afioen().enabled());
+        afio.mapr2
+            .modify(|_, w| unsafe { w.tim67_dac_dma_remap().bits(1) });
+
+        match channel {
+            Channel::One => {
+                dma1.ccr3.write(|w| unsafe { w.en().bits(0) });
+                dma1.cpar3.write(|w| unsafe { w.bits(par) });
+                dma1.cmar3.write(|w| unsafe { w.bits(mar) });
+                dma1.cndtr3.write(|w| unsafe { w.ndt().bits(ndt) });
+                dma1.ccr3
+                    .write(
+                        |w| unsafe {
+                            w.dir()         // memory to peripheral
+                                .bits(1)
+                                .psize()    // 16-bit
+                                .bits(0b01)
+                                .msize()    // 16-bit
+                                .bits(0b01)
+                                .minc()
+                                .bits(1)
+                                .circ()
+                                .bits(1)
+                                .en()
+                                .bits(1)
+                        },
+                    );
+            }
+            Channel::Two => {
+                dma1.ccr4.write(|w| unsafe { w.en().bits(0) });
+                dma1.cpar4.write(|w| unsafe { w.bits(par) });
+                dma1.cmar4.write(|w| unsafe { w.bits(mar) });
+                dma1.cndtr4.write(|w| unsafe { w.ndt().bits(ndt) });
+                dma1.ccr4
+                    .write(
+                        |w| unsafe {
+                            w.dir()         // memory to peripheral
+                                .bits(1)
+                                .psize()    // 16-bit
+                                .bits(0b01)
+                                .msize()    // 16-bit
+                                .bits(0b01)
+                                .minc()
+                                .bits(1)
+                                .circ()
+                                .bits(1)
+                                .en()
+                                .bits(1)
+                        },
+                    );
+            }
+        }
+
+        self.set_trigger(channel, Some(trigger));
+        match channel {
+            Channel::One => {
+                dac.cr
+                    .modify(|_, w| unsafe { w.dmaen1().bits(1).dmaudrie1().bits(1) })
+            }
+            Channel::Two => {
+                dac.cr
+                    .modify(|_, w| unsafe { w.dmaen2().bits(1).dmaudrie2().bits(1) })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops a waveform started with `play`
+    pub fn stop(&self, dma1: &Dma1, channel: Channel) {
+        match channel {
+            Channel::One => {
+                self.0.cr.modify(|_, w| unsafe { w.dmaen1().bits(0) });
+                dma1.ccr3.modify(|_, w| unsafe { w.en().bits(0) });
+            }
+            Channel::Two => {
+                self.0.cr.modify(|_, w| unsafe { w.dmaen2().bits(0) });
+                dma1.ccr4.modify(|_, w| unsafe { w.en().bits(0) });
+            }
+        }
+    }
+
+    /// Clears the DMA underrun flag of `channel`
+    ///
+    /// Returns `Err` if no underrun has occurred
+    pub fn clear_underrun_flag(&self, channel: Channel) -> Result<()> {
+        let dac = self.0;
+
+        match channel {
+            Channel::One => {
+                if dac.sr.read().dmaudr1().bits() == 0 {
+                    return Err(Error { _0: () });
+                }
+                // NOTE the flag is cleared by writing 1
+                dac.sr.write(|w| unsafe { w.dmaudr1().bits(1) });
+            }
+            Channel::Two => {
+                if dac.sr.read().dmaudr2().bits() == 0 {
+                    return Err(Error { _0: () });
+                }
+                dac.sr.write(|w| unsafe { w.dmaudr2().bits(1) });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Trigger frequency, in Hz, that plays a table of `len` samples at
+/// `frequency` Hz
+pub fn sample_rate(frequency: u32, len: usize) -> u32 {
+    frequency * u32(len)
+}
+
+/// Fills `table` with one period of a square wave between `low` and `high`
+pub fn square(table: &mut [u16], low: u16, high: u16) {
+    let half = table.len() / 2;
+
+    for (i, sample) in table.iter_mut().enumerate() {
+        *sample = if i < half { high } else { low };
+    }
+}
+
+/// Fills `table` with one period of a rising sawtooth between `low` and
+/// `high`
+pub fn sawtooth(table: &mut [u16], low: u16, high: u16) {
+    let len = u32(table.len());
+    let span = u32(high.saturating_sub(low));
+
+    for (i, sample) in table.iter_mut().enumerate() {
+        *sample = low + u16(span * u32(i) / len).unwrap();
+    }
+}
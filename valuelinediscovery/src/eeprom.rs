@@ -0,0 +1,287 @@
+//! EEPROM emulation on the flash, in the style of ST's AN2594
+//!
+//! Variables are 32-bit values identified by a 16-bit virtual address. Two
+//! banks of `BANK_PAGES` flash pages each, at the end of the flash, take
+//! turns: writes append a new entry to the active bank, and when it fills up
+//! the latest value of every variable is moved to the other bank, which
+//! becomes the active one. Every flash page is thus erased once per
+//! `ENTRIES` writes.
+//!
+//! Each bank starts with a status header:
+//!
+//! - `ERASED` (0xFFFF) - unused
+//! - `RECEIVE` (0xEEEE) - being filled by a transfer
+//! - `VALID` (0x0000) - active
+//!
+//! A transfer marks the new bank `RECEIVE`, copies the variables, erases the
+//! old bank and then marks the new bank `VALID`. Each entry carries a check
+//! word, written last. So whenever the power fails, `init` can tell from the
+//! headers and check words how far the last operation got, and finish it.
+//!
+//! NOTE the banks must not overlap the program; `memory.x` reserves the last
+//! two pages of the flash for them.
+
+use core::ptr;
+
+use cast::u32;
+
+use flash::{self, Flash};
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+#[derive(Clone, Copy, PartialEq)]
+pub enum Error {
+    /// Flash operation failed
+    Flash(flash::Error),
+    /// 0xFFFF is not a valid virtual address
+    Address,
+    /// The variable has never been written
+    NotFound,
+    /// There are more variables than fit in a bank
+    Full,
+    /// Neither bank is active; call `init` first
+    NoValidBank,
+}
+
+impl From<flash::Error> for Error {
+    fn from(error: flash::Error) -> Error {
+        Error::Flash(error)
+    }
+}
+
+/// Flash pages per bank
+pub const BANK_PAGES: u32 = 1;
+
+/// Size, in bytes, of a bank
+pub const BANK_SIZE: u32 = BANK_PAGES * flash::PAGE_SIZE;
+
+// Header, padded to the size of an entry
+const HEADER_SIZE: u32 = 8;
+// value (low), value (high), virtual address, check
+const ENTRY_SIZE: u32 = 8;
+
+/// Number of entries a bank holds
+pub const ENTRIES: u32 = (BANK_SIZE - HEADER_SIZE) / ENTRY_SIZE;
+
+// Bank status
+const ERASED: u16 = 0xFFFF;
+const RECEIVE: u16 = 0xEEEE;
+const VALID: u16 = 0x0000;
+
+/// Emulated EEPROM
+///
+/// NOTE the flash must be unlocked for `init`, `write` and `format`
+#[derive(Clone, Copy)]
+pub struct Eeprom<'a>(pub Flash<'a>);
+
+impl<'a> Eeprom<'a> {
+    /// Recovers the state of the banks, finishing any transfer interrupted
+    /// by a power failure
+    ///
+    /// Banks in an unknown state are formatted, which loses all the
+    /// variables.
+    pub fn init(&self) -> Result<()> {
+        let (a, b) = (bank(0), bank(1));
+
+        match (status(a), status(b)) {
+            (VALID, ERASED) => self.ensure_blank(b),
+            (ERASED, VALID) => self.ensure_blank(a),
+            // Transfer interrupted while copying
+            (VALID, RECEIVE) => self.transfer(a, b),
+            (RECEIVE, VALID) => self.transfer(b, a),
+            // Transfer interrupted after erasing the old bank
+            (ERASED, RECEIVE) => {
+                self.erase(a)?;
+                self.0.program(b, VALID)?;
+                Ok(())
+            }
+            (RECEIVE, ERASED) => {
+                self.erase(b)?;
+                self.0.program(a, VALID)?;
+                Ok(())
+            }
+            _ => self.format(),
+        }
+    }
+
+    /// Erases both banks and makes the first one active
+    pub fn format(&self) -> Result<()> {
+        self.erase(bank(0))?;
+        self.erase(bank(1))?;
+        self.0.program(bank(0), VALID)?;
+        Ok(())
+    }
+
+    /// Reads the variable at the virtual address `address`
+    pub fn read(&self, address: u16) -> Result<u32> {
+        if address == 0xFFFF {
+            return Err(Error::Address);
+        }
+
+        find(self.active()?, address).ok_or(Error::NotFound)
+    }
+
+    /// Writes `value` to the variable at the virtual address `address`
+    ///
+    /// Nothing is written if the variable already holds `value`
+    pub fn write(&self, address: u16, value: u32) -> Result<()> {
+        if address == 0xFFFF {
+            return Err(Error::Address);
+        }
+
+        let active = self.active()?;
+        if find(active, address) == Some(value) {
+            return Ok(());
+        }
+
+        let next = next_free(active);
+        if next < ENTRIES {
+            return self.append(active, next, address, value);
+        }
+
+        // The active bank is full: start over in the other bank with this
+        // value and carry the rest over
+        let other = if active == bank(0) { bank(1) } else { bank(0) };
+        self.ensure_blank(other)?;
+        self.0.program(other, RECEIVE)?;
+        self.append(other, 0, address, value)?;
+        self.transfer(active, other)
+    }
+
+    /// Start address of the active bank
+    fn active(&self) -> Result<u32> {
+        if status(bank(0)) == VALID {
+            Ok(bank(0))
+        } else if status(bank(1)) == VALID {
+            Ok(bank(1))
+        } else {
+            Err(Error::NoValidBank)
+        }
+    }
+
+    /// Moves the variables missing in the `RECEIVE` bank `to` from the bank
+    /// `from`, then retires `from`
+    fn transfer(&self, from: u32, to: u32) -> Result<()> {
+        // Newest entries first, so each variable is copied at most once
+        for i in (0..next_free(from)).rev() {
+            if let Some((address, value)) = entry(from, i) {
+                if find(to, address).is_none() {
+                    let next = next_free(to);
+                    if next == ENTRIES {
+                        return Err(Error::Full);
+                    }
+                    self.append(to, next, address, value)?;
+                }
+            }
+        }
+
+        self.erase(from)?;
+        self.0.program(to, VALID)?;
+        Ok(())
+    }
+
+    /// Writes the entry `i` of `bank`
+    fn append(
+        &self,
+        bank: u32,
+        i: u32,
+        address: u16,
+        value: u32,
+    ) -> Result<()> {
+        let entry = [
+            value as u16,
+            (value >> 16) as u16,
+            address,
+            check(address, value),
+        ];
+
+        self.0.write(slot(bank, i), &entry)?;
+        Ok(())
+    }
+
+    /// Erases `bank` unless it's already blank
+    fn ensure_blank(&self, bank: u32) -> Result<()> {
+        let blank = (0..BANK_SIZE / 4).all(|i| unsafe {
+            ptr::read_volatile((bank + 4 * i) as *const u32) == 0xFFFF_FFFF
+        });
+
+        if blank { Ok(()) } else { self.erase(bank) }
+    }
+
+    /// Erases all the pages of `bank`
+    fn erase(&self, bank: u32) -> Result<()> {
+        for page in 0..BANK_PAGES {
+            self.0.erase_page(bank + page * flash::PAGE_SIZE)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Start address of the bank `i`
+fn bank(i: u32) -> u32 {
+    flash::page_address(flash::pages() - 2 * BANK_PAGES + i * BANK_PAGES)
+}
+
+/// Status header of `bank`
+fn status(bank: u32) -> u16 {
+    half_word(bank)
+}
+
+/// Address of the entry `i` of `bank`
+fn slot(bank: u32, i: u32) -> u32 {
+    bank + HEADER_SIZE + i * ENTRY_SIZE
+}
+
+/// Returns the entry `i` of `bank`, unless it's blank or torn
+fn entry(bank: u32, i: u32) -> Option<(u16, u32)> {
+    let slot = slot(bank, i);
+
+    let value = u32(half_word(slot)) | u32(half_word(slot + 2)) << 16;
+    let address = half_word(slot + 4);
+
+    if address != 0xFFFF && half_word(slot + 6) == check(address, value) {
+        Some((address, value))
+    } else {
+        None
+    }
+}
+
+/// Index of the entry after the last one in use in `bank`
+///
+/// Torn entries count as used: their slots can't be programmed again
+fn next_free(bank: u32) -> u32 {
+    let mut next = ENTRIES;
+    while next > 0 {
+        let slot = slot(bank, next - 1);
+        let blank = (0..ENTRY_SIZE / 2)
+            .all(|j| half_word(slot + 2 * j) == 0xFFFF);
+        if !blank {
+            break;
+        }
+        next -= 1;
+    }
+
+    next
+}
+
+/// Latest value of the variable `address` in `bank`
+fn find(bank: u32, address: u16) -> Option<u32> {
+    (0..next_free(bank))
+        .rev()
+        .filter_map(|i| entry(bank, i))
+        .find(|&(a, _)| a == address)
+        .map(|(_, value)| value)
+}
+
+/// Check word of an entry
+fn check(address: u16, value: u32) -> u16 {
+    !(address ^ value as u16 ^ (value >> 16) as u16).rotate_left(5)
+}
+
+/// Reads the half-word at `address`
+fn half_word(address: u32) -> u16 {
+    unsafe { ptr::read_volatile(address as *const u16) }
+}
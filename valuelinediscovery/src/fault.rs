@@ -0,0 +1,462 @@
+//! HardFault handler that records and explains the fault
+//!
+//! `hard_fault` captures the registers the core stacked on exception entry,
+//! the fault status registers (`CFSR`, `HFSR`) and the fault addresses
+//! (`MMFAR`, `BFAR`). It then
+//!
+//! 1. stops at a breakpoint, if a debugger is connected
+//! 2. saves them in `.noinit` RAM, for `last_fault` to pick up on the next
+//!    boot, and the PC in the crash record of `reset` too
+//! 3. logs them, with their causes spelled out, through `log`: over ITM or
+//!    the serial port, whatever `log::init` chose
+//! 4. resets the device
+//!
+//! `hard_fault` is in the exception vector this crate provides (the
+//! "exceptions" feature). MemManage, BusFault and UsageFault are left
+//! disabled, so they all escalate to HardFault with their status intact.
+//!
+//! The `.noinit` record survives resets but not power loss, and only if
+//! the RAM it's in isn't touched in between: the first 256 bytes of RAM are
+//! reserved for it, and the one of `panic`, in all the memory layouts, the
+//! bootloader's included. The crash record of `reset`, with the
+//! "crash-record" feature, is in the backup registers, which keep it through
+//! power loss when VBAT is present.
+
+use core::{fmt, intrinsics, ptr};
+
+use cortex_m::{asm, exception};
+use stm32f100::{Scb, DCB, SCB};
+
+use reset;
+#[cfg(feature = "crash-record")]
+use reset::Crash;
+
+// SCB_CCR
+const DIV_0_TRP: u32 = 1 << 4;
+const UNALIGN_TRP: u32 = 1 << 3;
+
+// SCB_CFSR
+const MMARVALID: u32 = 1 << 7;
+const BFARVALID: u32 = 1 << 15;
+
+// DCB_DHCSR: a debugger is connected
+const C_DEBUGEN: u32 = 1 << 0;
+
+// Tag of a valid record
+const MAGIC: u32 = 0xFA17_FA17;
+
+// Where the stacked registers can be
+const RAM_START: u32 = 0x2000_0000;
+
+extern "C" {
+    // Top of the RAM, from `memory.x`
+    static _stack_start: u32;
+}
+
+/// Registers stacked on exception entry
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Frame {
+    /// R0
+    pub r0: u32,
+    /// R1
+    pub r1: u32,
+    /// R2
+    pub r2: u32,
+    /// R3
+    pub r3: u32,
+    /// R12
+    pub r12: u32,
+    /// Link register: where the faulting function would have returned
+    pub lr: u32,
+    /// Program counter: the faulting instruction, or the next one
+    pub pc: u32,
+    /// Program status register
+    pub xpsr: u32,
+}
+
+const NO_FRAME: Frame = Frame {
+    r0: 0,
+    r1: 0,
+    r2: 0,
+    r3: 0,
+    r12: 0,
+    lr: 0,
+    pc: 0,
+    xpsr: 0,
+};
+
+/// State of the core at the fault
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Fault {
+    /// Stacked registers; all zero if the stack pointer was off the RAM
+    pub frame: Frame,
+    /// Stack pointer of the faulting code, after stacking: where `frame` is
+    pub sp: u32,
+    /// Configurable fault status register
+    pub cfsr: u32,
+    /// HardFault status register
+    pub hfsr: u32,
+    /// MemManage fault address register
+    pub mmfar: u32,
+    /// BusFault address register
+    pub bfar: u32,
+}
+
+impl Fault {
+    /// Causes of the fault, as flagged in `CFSR` and `HFSR`
+    pub fn causes(&self) -> Causes {
+        Causes {
+            fault: *self,
+            bit: 0,
+        }
+    }
+
+    /// Cause flagged by bit `bit` of `CFSR`, or bit `bit - 32` of `HFSR`
+    fn cause(&self, bit: u32) -> Option<Cause> {
+        let status = if bit < 32 {
+            self.cfsr >> bit
+        } else {
+            self.hfsr >> (bit - 32)
+        };
+        if status & 1 == 0 {
+            return None;
+        }
+
+        let mmfar = if self.cfsr & MMARVALID != 0 {
+            Some(self.mmfar)
+        } else {
+            None
+        };
+        let bfar = if self.cfsr & BFARVALID != 0 {
+            Some(self.bfar)
+        } else {
+            None
+        };
+
+        Some(match bit {
+            0 => Cause::InstructionAccessViolation,
+            1 => Cause::DataAccessViolation(mmfar),
+            3 | 11 => Cause::Unstacking,
+            4 | 12 => Cause::StackOverflow,
+            8 => Cause::InstructionBusError,
+            9 => Cause::PreciseBusError(bfar),
+            10 => Cause::ImpreciseBusError,
+            16 => Cause::UndefinedInstruction,
+            17 => Cause::InvalidState,
+            18 => Cause::InvalidExcReturn,
+            19 => Cause::NoCoprocessor,
+            24 => Cause::UnalignedAccess,
+            25 => Cause::DivideByZero,
+            33 => Cause::VectorTableRead,
+            63 => Cause::DebugEvent,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = &self.frame;
+        write!(
+            f,
+            "HardFault at PC {:#010x}, LR {:#010x}, SP {:#010x}, \
+             xPSR {:#010x}, R0-R3 {:#x} {:#x} {:#x} {:#x}, R12 {:#x}; \
+             CFSR {:#010x}, HFSR {:#010x}",
+            frame.pc,
+            frame.lr,
+            self.sp,
+            frame.xpsr,
+            frame.r0,
+            frame.r1,
+            frame.r2,
+            frame.r3,
+            frame.r12,
+            self.cfsr,
+            self.hfsr
+        )
+    }
+}
+
+/// Iterator over the causes of a fault
+pub struct Causes {
+    fault: Fault,
+    bit: u32,
+}
+
+impl Iterator for Causes {
+    type Item = Cause;
+
+    fn next(&mut self) -> Option<Cause> {
+        while self.bit < 64 {
+            let bit = self.bit;
+            self.bit += 1;
+
+            if let Some(cause) = self.fault.cause(bit) {
+                return Some(cause);
+            }
+        }
+
+        None
+    }
+}
+
+/// A cause of a fault
+#[derive(Clone, Copy, PartialEq)]
+pub enum Cause {
+    /// Instruction fetch from a region the MPU forbids, or that can't be
+    /// executed
+    InstructionAccessViolation,
+    /// Data access the MPU forbids, at the address if known
+    DataAccessViolation(Option<u32>),
+    /// Returning from an exception, popping the stacked registers failed
+    Unstacking,
+    /// Entering an exception, pushing the registers failed: the stack
+    /// pointer ran off the RAM
+    StackOverflow,
+    /// Instruction fetch from an address nothing answers
+    InstructionBusError,
+    /// Data access to an address nothing answers, that address if known
+    PreciseBusError(Option<u32>),
+    /// Data access to an address nothing answers, found out after the
+    /// fact: usually a buffered write; the PC is past the culprit
+    ImpreciseBusError,
+    /// Undefined instruction, e.g. jumping into data
+    UndefinedInstruction,
+    /// Switch to ARM state, e.g. calling a function pointer with an even
+    /// address
+    InvalidState,
+    /// Exception return with an invalid EXC_RETURN value
+    InvalidExcReturn,
+    /// Coprocessor instruction; there's no coprocessor
+    NoCoprocessor,
+    /// Unaligned access, with `trap_unaligned`, or by LDM, STM, LDRD or
+    /// STRD
+    UnalignedAccess,
+    /// Division by zero, with `init`
+    DivideByZero,
+    /// Reading the vector table on exception entry failed
+    VectorTableRead,
+    /// Debug event, with no debugger connected
+    DebugEvent,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Cause::InstructionAccessViolation => {
+                f.write_str("instruction access violation")
+            }
+            Cause::DataAccessViolation(Some(address)) => {
+                write!(f, "data access violation at {:#010x}", address)
+            }
+            Cause::DataAccessViolation(None) => {
+                f.write_str("data access violation")
+            }
+            Cause::Unstacking => {
+                f.write_str(
+                    "couldn't unstack the registers on exception return",
+                )
+            }
+            Cause::StackOverflow => {
+                f.write_str(
+                    "stack overflow: couldn't stack the registers on \
+                     exception entry",
+                )
+            }
+            Cause::InstructionBusError => f.write_str("instruction bus error"),
+            Cause::PreciseBusError(Some(address)) => {
+                write!(f, "precise bus error at {:#010x}", address)
+            }
+            Cause::PreciseBusError(None) => f.write_str("precise bus error"),
+            Cause::ImpreciseBusError => {
+                f.write_str("imprecise bus error, the PC is past the culprit")
+            }
+            Cause::UndefinedInstruction => {
+                f.write_str("undefined instruction")
+            }
+            Cause::InvalidState => {
+                f.write_str("invalid state: attempt to switch to ARM state")
+            }
+            Cause::InvalidExcReturn => f.write_str("invalid EXC_RETURN"),
+            Cause::NoCoprocessor => f.write_str("no coprocessor"),
+            Cause::UnalignedAccess => f.write_str("unaligned access"),
+            Cause::DivideByZero => f.write_str("divide by zero"),
+            Cause::VectorTableRead => {
+                f.write_str("bus fault reading the vector table")
+            }
+            Cause::DebugEvent => f.write_str("debug event"),
+        }
+    }
+}
+
+// Stack of `handler`; `u64`s to keep it 8-byte aligned
+static mut STACK: [u64; 128] = [0; 128];
+
+/// The record in `.noinit`
+#[repr(C)]
+struct Record {
+    magic: u32,
+    fault: Fault,
+    check: u32,
+}
+
+#[link_section = ".noinit"]
+static mut RECORD: Record = Record {
+    magic: 0,
+    fault: Fault {
+        frame: NO_FRAME,
+        sp: 0,
+        cfsr: 0,
+        hfsr: 0,
+        mmfar: 0,
+        bfar: 0,
+    },
+    check: 0,
+};
+
+/// Makes integer division by zero fault
+///
+/// NOTE Rust checks divisions itself, and panics; this only catches the
+/// ones in assembly or C
+pub fn init(scb: &Scb) {
+    unsafe { scb.ccr.modify(|r| r | DIV_0_TRP) }
+}
+
+/// Makes every unaligned access fault
+///
+/// Without this only LDM, STM, LDRD and STRD fault on unaligned addresses.
+pub fn trap_unaligned(scb: &Scb) {
+    unsafe { scb.ccr.modify(|r| r | UNALIGN_TRP) }
+}
+
+/// Returns the fault that reset the device, if any, and clears it
+///
+/// Call it early: nothing else must have used the `.noinit` RAM
+pub fn last_fault() -> Option<Fault> {
+    unsafe {
+        let record = ptr::read_volatile(&RECORD);
+        ptr::write_volatile(&mut RECORD.magic, 0);
+
+        if record.magic == MAGIC && record.check == check(&record.fault) {
+            Some(record.fault)
+        } else {
+            None
+        }
+    }
+}
+
+/// Writes `fault` and its causes to `w`, one per line
+pub fn report<W>(w: &mut W, fault: &Fault) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    write!(w, "{}\r\n", fault)?;
+    for cause in fault.causes() {
+        write!(w, "  {}\r\n", cause)?;
+    }
+
+    Ok(())
+}
+
+/// HardFault exception handler: records the fault, then resets
+///
+/// Passes the stack pointer of the faulting code, the one EXC_RETURN (LR)
+/// says, on to `handler`, which runs on a stack of its own: after a
+/// stacking fault MSP points out of the RAM
+#[naked]
+pub extern "C" fn hard_fault(_: exception::HardFault) {
+    // NOTE(1024) the size of `STACK`, in bytes
+    unsafe {
+        asm!("tst lr, #4
+              ite eq
+              mrseq r0, MSP
+              mrsne r0, PSP
+              movw r1, :lower16:$1
+              movt r1, :upper16:$1
+              add r1, r1, #1024
+              msr MSP, r1
+              b $0"
+             :
+             : "i"(handler as extern "C" fn(u32) -> !),
+               "i"(&STACK as *const _)
+             : "r0", "r1"
+             : "volatile");
+        intrinsics::unreachable()
+    }
+}
+
+extern "C" fn handler(sp: u32) -> ! {
+    // NOTE(unsafe) the program is over; only the core registers are used
+    let scb = unsafe { &*SCB.get() };
+    let dcb = unsafe { &*DCB.get() };
+
+    // NOTE the stacked registers can't be read if stacking failed
+    let ram_end = unsafe { &_stack_start as *const u32 as u32 };
+    // NOTE(checked_add) PSP can hold anything after a fault
+    let in_ram = sp >= RAM_START && sp % 4 == 0 &&
+        sp.checked_add(32).map_or(false, |end| end <= ram_end);
+    let frame = if in_ram {
+        unsafe { ptr::read_volatile(sp as *const Frame) }
+    } else {
+        NO_FRAME
+    };
+
+    let fault = Fault {
+        frame: frame,
+        sp: sp,
+        cfsr: scb.cfsr.read(),
+        hfsr: scb.hfsr.read(),
+        mmfar: scb.mmar.read(),
+        bfar: scb.bfar.read(),
+    };
+
+    if dcb.dhcsr.read() & C_DEBUGEN != 0 {
+        // Inspect `fault` here; `continue` to record it and reset
+        asm::bkpt();
+    }
+
+    unsafe {
+        ptr::write_volatile(
+            &mut RECORD,
+            Record {
+                magic: MAGIC,
+                fault: fault,
+                check: check(&fault),
+            },
+        );
+    }
+    #[cfg(feature = "crash-record")]
+    reset::record(Crash::HardFault { pc: fault.frame.pc });
+
+    error!("{}", fault);
+    for cause in fault.causes() {
+        error!("{}", cause);
+    }
+
+    reset::system_reset(scb)
+}
+
+/// Check word of a record
+fn check(fault: &Fault) -> u32 {
+    let words = [
+        fault.frame.r0,
+        fault.frame.r1,
+        fault.frame.r2,
+        fault.frame.r3,
+        fault.frame.r12,
+        fault.frame.lr,
+        fault.frame.pc,
+        fault.frame.xpsr,
+        fault.sp,
+        fault.cfsr,
+        fault.hfsr,
+        fault.mmfar,
+        fault.bfar,
+    ];
+
+    words
+        .iter()
+        .fold(!MAGIC, |sum, word| sum.rotate_left(1) ^ word)
+}
@@ -0,0 +1,392 @@
+//! In-application flash programming
+//!
+//! The flash is split in pages of `PAGE_SIZE` bytes, the smallest unit that
+//! can be erased. Erased flash reads as `0xFFFF`; a half-word can only be
+//! programmed once between erases.
+//!
+//! The CPU can't fetch instructions from the flash while it's being
+//! programmed or erased, so the code that starts an operation and waits for
+//! it runs from RAM. Interrupt handlers still live in the flash though: they
+//! are delayed until the operation is over (up to 40 ms for a page erase).
+//!
+//! The option bytes hold the read and write protection and the user
+//! configuration. They are erased and programmed as a whole with
+//! `Flash::program_option_bytes`, and only take effect after a reset (see
+//! `reset::system_reset`).
+
+use core::{cmp, intrinsics, ptr};
+
+use cast::{u16, u32};
+use stm32f100;
+
+use signature;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+#[derive(Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The address is out of the flash, or not aligned
+    Address,
+    /// The flash controller is locked, see `Flash::unlock`
+    Locked,
+    /// The half-word wasn't erased before programming it
+    Programming,
+    /// The page is write protected
+    WriteProtected,
+    /// The flash doesn't read back what was programmed
+    Verify,
+    /// Read protection is active, see `Flash::program_option_bytes`
+    ReadProtected,
+}
+
+/// Start address of the flash
+pub const START: u32 = 0x0800_0000;
+
+/// Size, in bytes, of a flash page
+#[cfg(not(feature = "high-density"))]
+pub const PAGE_SIZE: u32 = 1024;
+
+/// Size, in bytes, of a flash page
+#[cfg(feature = "high-density")]
+pub const PAGE_SIZE: u32 = 2048;
+
+/// Size, in bytes, of the flash area covered by one write protection bit
+///
+/// NOTE on high density parts, the last bit covers the rest of the flash
+pub const SECTOR_SIZE: u32 = 4096;
+
+// FLASH_KEYR and FLASH_OPTKEYR keys
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+// FLASH_SR
+const BSY: u32 = 1 << 0;
+const PGERR: u32 = 1 << 2;
+const WRPRTERR: u32 = 1 << 4;
+const EOP: u32 = 1 << 5;
+
+// FLASH_CR
+const STRT: u32 = 1 << 6;
+
+// Option bytes: RDP, USER, Data0, Data1, WRP0 - WRP3
+const OPTION_BYTES: u32 = 0x1FFF_F800;
+// RDP value that disables the read protection
+const RDP_KEY: u16 = 0x00A5;
+
+/// Option bytes
+#[derive(Clone, Copy, PartialEq)]
+pub struct OptionBytes {
+    /// Read protection: the flash can't be read by the debugger or by code
+    /// running from RAM
+    pub read_protection: bool,
+    /// Write protected sectors, one bit per `SECTOR_SIZE` bytes
+    pub write_protection: u32,
+    /// The IWDG starts by itself at reset and can't be stopped
+    pub hardware_watchdog: bool,
+    /// Entering Stop mode resets the device
+    pub reset_on_stop: bool,
+    /// Entering Standby mode resets the device
+    pub reset_on_standby: bool,
+    /// User data bytes
+    pub data: [u8; 2],
+}
+
+/// Write protection bit of the sector that contains `address`
+pub fn sector(address: u32) -> u32 {
+    1 << cmp::min((address - START) / SECTOR_SIZE, 31)
+}
+
+/// Size of the flash, in bytes, as reported by the device
+pub fn size() -> u32 {
+    u32(signature::flash_size()) * 1024
+}
+
+/// Number of flash pages
+pub fn pages() -> u32 {
+    size() / PAGE_SIZE
+}
+
+/// Start address of the flash page `page`
+pub fn page_address(page: u32) -> u32 {
+    START + page * PAGE_SIZE
+}
+
+/// Flash controller
+#[derive(Clone, Copy)]
+pub struct Flash<'a>(pub &'a stm32f100::Flash);
+
+impl<'a> Flash<'a> {
+    /// Unlocks the flash controller for programming and erasing
+    pub fn unlock(&self) {
+        let flash = self.0;
+
+        if self.is_locked() {
+            flash.keyr.write(|w| unsafe { w.key().bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.key().bits(KEY2) });
+        }
+    }
+
+    /// Locks the flash controller until the next `unlock`
+    pub fn lock(&self) {
+        self.0.cr.modify(|_, w| unsafe { w.lock().bits(1) });
+    }
+
+    /// Returns `true` if the flash controller is locked
+    pub fn is_locked(&self) -> bool {
+        self.0.cr.read().lock().bits() == 1
+    }
+
+    /// Erases the page that contains `address`
+    ///
+    /// Returns `Err` if `address` is out of the flash
+    pub fn erase_page(&self, address: u32) -> Result<()> {
+        let flash = self.0;
+
+        check(address, 1)?;
+        self.ready()?;
+
+        flash.cr.modify(|_, w| unsafe { w.per().bits(1) });
+        flash.ar.write(|w| unsafe { w.far().bits(address) });
+        let result = start_and_wait(flash);
+        flash.cr.modify(|_, w| unsafe { w.per().bits(0) });
+        result?;
+
+        let start = address - (address - START) % PAGE_SIZE;
+        verify_erased(start, PAGE_SIZE)
+    }
+
+    /// Erases the whole flash
+    ///
+    /// # Safety
+    ///
+    /// This erases the program that's running too: nothing but code in RAM
+    /// can run afterwards, and the device won't boot again from flash.
+    pub unsafe fn erase_all(&self) -> Result<()> {
+        let flash = self.0;
+
+        self.ready()?;
+
+        flash.cr.modify(|_, w| w.mer().bits(1));
+        let result = start_and_wait(flash);
+        flash.cr.modify(|_, w| w.mer().bits(0));
+        result
+    }
+
+    /// Programs the half-word at `address` with `half_word`
+    ///
+    /// Returns `Err` if `address` is out of the flash or not half-word
+    /// aligned, or if the half-word wasn't erased
+    pub fn program(&self, address: u32, half_word: u16) -> Result<()> {
+        let flash = self.0;
+
+        check(address, 2)?;
+        if address % 2 != 0 {
+            return Err(Error::Address);
+        }
+        self.ready()?;
+
+        flash.cr.modify(|_, w| unsafe { w.pg().bits(1) });
+        let result = program_and_wait(flash, address, half_word);
+        flash.cr.modify(|_, w| unsafe { w.pg().bits(0) });
+        result?;
+
+        if unsafe { ptr::read_volatile(address as *const u16) } != half_word {
+            Err(Error::Verify)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Programs `half_words` starting at `address`
+    ///
+    /// Stops at the first half-word that fails
+    pub fn write(&self, address: u32, half_words: &[u16]) -> Result<()> {
+        check(address, 2 * u32(half_words.len()))?;
+
+        for (i, half_word) in half_words.iter().enumerate() {
+            self.program(address + 2 * u32(i), *half_word)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the option bytes loaded at the last reset
+    pub fn option_bytes(&self) -> OptionBytes {
+        let flash = self.0;
+
+        let obr = flash.obr.read();
+        OptionBytes {
+            read_protection: obr.rdprt().bits() == 1,
+            write_protection: !flash.wrpr.read().wrp().bits(),
+            hardware_watchdog: obr.wdg_sw().bits() == 0,
+            reset_on_stop: obr.n_rst_stop().bits() == 0,
+            reset_on_standby: obr.n_rst_stdby().bits() == 0,
+            data: [obr.data0().bits(), obr.data1().bits()],
+        }
+    }
+
+    /// Erases the option bytes and programs them with `options`
+    ///
+    /// The new values take effect after the next reset; `option_bytes`
+    /// keeps returning the old ones until then.
+    ///
+    /// Returns `Err` if the read protection is active: erasing the option
+    /// bytes would then make the hardware erase the whole flash, including
+    /// this program. Lift the read protection with a debugger instead.
+    pub fn program_option_bytes(&self, options: &OptionBytes) -> Result<()> {
+        let flash = self.0;
+
+        if flash.obr.read().rdprt().bits() == 1 {
+            return Err(Error::ReadProtected);
+        }
+        self.ready()?;
+
+        flash.optkeyr.write(|w| unsafe { w.optkey().bits(KEY1) });
+        flash.optkeyr.write(|w| unsafe { w.optkey().bits(KEY2) });
+
+        let result = self.erase_option_bytes().and_then(
+            |_| self.program_options(options),
+        );
+
+        flash.cr.modify(|_, w| unsafe { w.optwre().bits(0) });
+        result
+    }
+
+    /// Erases the option bytes; needs OPTWRE
+    fn erase_option_bytes(&self) -> Result<()> {
+        let flash = self.0;
+
+        flash.cr.modify(|_, w| unsafe { w.opter().bits(1) });
+        let result = start_and_wait(flash);
+        flash.cr.modify(|_, w| unsafe { w.opter().bits(0) });
+        result
+    }
+
+    /// Programs the erased option bytes with `options`; needs OPTWRE
+    fn program_options(&self, options: &OptionBytes) -> Result<()> {
+        let flash = self.0;
+
+        // The hardware programs the complement in the upper byte
+        let user = 0xF8 | if options.hardware_watchdog { 0 } else { 1 } |
+            if options.reset_on_stop { 0 } else { 1 << 1 } |
+            if options.reset_on_standby { 0 } else { 1 << 2 };
+        let wrp = !options.write_protection;
+
+        // NOTE RDP first: an erased RDP byte means read protection
+        let bytes = [
+            if options.read_protection { 0x00 } else { RDP_KEY },
+            user,
+            u16(options.data[0]),
+            u16(options.data[1]),
+            u16(wrp & 0xFF).unwrap(),
+            u16((wrp >> 8) & 0xFF).unwrap(),
+            u16((wrp >> 16) & 0xFF).unwrap(),
+            u16(wrp >> 24).unwrap(),
+        ];
+
+        flash.cr.modify(|_, w| unsafe { w.optpg().bits(1) });
+        let mut result = Ok(());
+        for (i, byte) in bytes.iter().enumerate() {
+            let address = OPTION_BYTES + 2 * u32(i);
+            result = program_and_wait(flash, address, *byte);
+            if result.is_err() {
+                break;
+            }
+        }
+        flash.cr.modify(|_, w| unsafe { w.optpg().bits(0) });
+        result
+    }
+
+    /// Waits for an ongoing operation and checks the controller is unlocked
+    fn ready(&self) -> Result<()> {
+        let flash = self.0;
+
+        while flash.sr.read().bsy().bits() == 1 {}
+
+        if self.is_locked() {
+            Err(Error::Locked)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks that `len` bytes starting at `address` are in the flash
+fn check(address: u32, len: u32) -> Result<()> {
+    if address < START || address - START > size() ||
+        len > size() - (address - START)
+    {
+        Err(Error::Address)
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks that `len` bytes starting at `address` read as erased
+fn verify_erased(address: u32, len: u32) -> Result<()> {
+    for i in 0..len / 4 {
+        let word =
+            unsafe { ptr::read_volatile((address + 4 * i) as *const u32) };
+        if word != 0xFFFF_FFFF {
+            return Err(Error::Verify);
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE the functions below run from RAM. They access the registers with
+// the volatile intrinsics, which are always inlined, instead of the
+// register API or `ptr::{read,write}_volatile`: those are calls into the
+// flash in debug builds.
+
+/// Starts the selected erase and waits for it to finish
+// NOTE(link_section) runs from RAM; `.data.*` is copied there at startup
+#[inline(never)]
+#[link_section = ".data.flash"]
+fn start_and_wait(flash: &stm32f100::Flash) -> Result<()> {
+    let cr = &flash.cr as *const _ as *mut u32;
+    unsafe {
+        let bits = intrinsics::volatile_load(cr);
+        intrinsics::volatile_store(cr, bits | STRT);
+    }
+    wait(flash)
+}
+
+/// Programs `half_word` at `address` and waits for it to finish
+#[inline(never)]
+#[link_section = ".data.flash"]
+fn program_and_wait(
+    flash: &stm32f100::Flash,
+    address: u32,
+    half_word: u16,
+) -> Result<()> {
+    unsafe { intrinsics::volatile_store(address as *mut u16, half_word) }
+    wait(flash)
+}
+
+/// Waits for the end of an operation, then reports and clears its status
+#[inline(always)]
+fn wait(flash: &stm32f100::Flash) -> Result<()> {
+    let sr = &flash.sr as *const _ as *mut u32;
+
+    let mut status = unsafe { intrinsics::volatile_load(sr) };
+    while status & BSY != 0 {
+        status = unsafe { intrinsics::volatile_load(sr) };
+    }
+
+    let result = if status & WRPRTERR != 0 {
+        Err(Error::WriteProtected)
+    } else if status & PGERR != 0 {
+        Err(Error::Programming)
+    } else {
+        Ok(())
+    };
+
+    // NOTE(write) the status flags are cleared by writing 1
+    unsafe { intrinsics::volatile_store(sr, EOP | PGERR | WRPRTERR) }
+
+    result
+}
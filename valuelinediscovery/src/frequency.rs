@@ -1,3 +1,3 @@
-// pub const AHB: u32 = 8_000_000;
+pub const AHB: u32 = 8_000_000;
 pub const APB1: u32 = 8_000_000;
 pub const APB2: u32 = 8_000_000;
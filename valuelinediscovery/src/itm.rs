@@ -0,0 +1,132 @@
+//! Instrumentation Trace Macrocell, over the SWO pin
+//!
+//! - SWO - PB3 (TRACESWO)
+//!
+//! `init` sets up the TPIU to send the ITM packets out of SWO as UART
+//! frames (NRZ) at a baud rate derived from the core clock, and enables all
+//! 32 stimulus ports. A `Port` is one of those ports as an `fmt::Write`r.
+//!
+//! On the host, have OpenOCD capture the stream at the same baud rate, e.g.
+//! `monitor tpiu config internal itm.fifo uart off 8000000 2000000` for the
+//! default, and decode it with `tools/stm32-itm`. OpenOCD's own `tpiu config`
+//! overwrites what `init` did; `init` is for when no debugger sets up the
+//! trace, e.g. a standalone SWO probe.
+//!
+//! NOTE writes to a port that isn't enabled, by `init` or by a debugger, are
+//! dropped rather than blocking forever
+
+use core::{fmt, ptr};
+
+use cast::usize;
+use cortex_m::itm;
+use stm32f100::{Dbg, Dcb, Itm, Tpiu, ITM};
+
+use frequency;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// Number of stimulus ports
+pub const PORTS: u8 = 32;
+
+/// Default SWO baud rate
+pub const DEFAULT_BAUD_RATE: u32 = 2_000_000;
+
+/// The TPIU is clocked by the core clock
+pub const CLOCK: u32 = frequency::AHB;
+
+// DCB_DEMCR: enables the DWT and ITM
+const TRCENA: u32 = 1 << 24;
+
+// TPIU_SPPR: asynchronous SWO, NRZ encoding
+const NRZ: u32 = 0b10;
+
+// TPIU_FFCR: formatter bypassed, as SWO needs. NOTE not in `Tpiu` of
+// `cortex-m`
+const TPIU_FFCR: u32 = 0xE004_0304;
+const TRIGIN: u32 = 1 << 8;
+
+// ITM_LAR: unlocks the ITM registers
+const UNLOCK: u32 = 0xC5AC_CE55;
+
+// ITM_TCR
+const ITMENA: u32 = 1 << 0;
+const SYNCENA: u32 = 1 << 2;
+const TRACE_BUS_ID: u32 = 1 << 16;
+
+/// Enables the ITM and sends its output out of SWO at `baud_rate` bits per
+/// second
+///
+/// Returns `Err` if the core clock isn't a multiple of `baud_rate`: the
+/// TPIU can only divide it
+pub fn init(
+    dbg: &Dbg,
+    dcb: &Dcb,
+    itm: &Itm,
+    tpiu: &Tpiu,
+    baud_rate: u32,
+) -> Result<()> {
+    if baud_rate == 0 || baud_rate > CLOCK || CLOCK % baud_rate != 0 {
+        return Err(Error { _0: () });
+    }
+
+    unsafe {
+        dcb.demcr.modify(|r| r | TRCENA);
+
+        // PB3 as TRACESWO, asynchronous mode
+        dbg.cr.modify(|_, w| w.trace_ioen().bits(1).trace_mode().bits(0b00));
+
+        tpiu.cspsr.write(1);
+        tpiu.acpr.write(CLOCK / baud_rate - 1);
+        tpiu.sppr.write(NRZ);
+        ptr::write_volatile(TPIU_FFCR as *mut u32, TRIGIN);
+
+        itm.lar.write(UNLOCK);
+        itm.tcr.write(TRACE_BUS_ID | SYNCENA | ITMENA);
+        itm.ter[0].write(0xFFFF_FFFF);
+    }
+
+    Ok(())
+}
+
+/// Returns `true` if the ITM is enabled and so is stimulus `port`
+pub fn is_enabled(port: u8) -> bool {
+    if port >= PORTS {
+        return false;
+    }
+
+    // NOTE(unsafe) read only accesses
+    let itm = unsafe { &*ITM.get() };
+    itm.tcr.read() & ITMENA != 0 && itm.ter[0].read() & (1 << port) != 0
+}
+
+/// A stimulus port
+#[derive(Clone, Copy)]
+pub struct Port(pub u8);
+
+impl Port {
+    /// Sends `bytes`, blocking while the ITM FIFO is full
+    ///
+    /// The bytes are dropped if the port isn't enabled.
+    pub fn write_all(&self, bytes: &[u8]) {
+        if is_enabled(self.0) {
+            // NOTE(unsafe) stimulus ports are write only FIFOs; a write
+            // preempted by another write to the same port interleaves the
+            // two, but doesn't lose data
+            let itm = unsafe { &*ITM.get() };
+            itm::write_all(&itm.stim[usize(self.0)], bytes);
+        }
+    }
+}
+
+impl fmt::Write for Port {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes());
+        Ok(())
+    }
+}
@@ -10,11 +10,9 @@
 /// DB6     PC2     Output      Data line
 /// DB7     PC3     Output      Data line (MSB)
 
-/// !!!FIXME!!! timing screws up in release build
-
 use stm32f100::{Gpioc, Rcc};
 
-use cortex_m::asm;
+use time;
 
 /// LCD Module Register Type
 #[derive(Copy,Clone,PartialEq)]
@@ -177,9 +175,7 @@ impl<'a> Lcd<'a> {
     /// Clear the display
     pub fn clear(self) {
         self.word(Register::Instruction, Operation::Write, 0x01);
-        for _ in 0..40 { // 1.6ms min
-            asm::nop();
-        }
+        time::delay_us(1_600);
     }
 
     /// Write an ascii string (`u8` slice) to the display
@@ -17,6 +17,11 @@
 
 //#![deny(missing_docs)]
 //#![deny(warnings)]
+#![feature(asm)]
+#![feature(const_fn)]
+#![feature(core_intrinsics)]
+#![feature(naked_functions)]
+#![feature(used)]
 #![no_std]
 
 extern crate cast;
@@ -31,9 +36,43 @@ pub mod led;
 pub mod serial;
 pub mod timer;
 pub mod button;
+pub mod adc;
+pub mod dac;
+pub mod rtc;
+pub mod backup;
+pub mod power;
+pub mod watchdog;
+pub mod reset;
+pub mod flash;
+pub mod eeprom;
+pub mod update;
+pub mod crc;
+pub mod signature;
+pub mod time;
+pub mod profile;
+pub mod soft_timer;
+pub mod itm;
+#[macro_use]
+pub mod log;
+pub mod binlog;
+pub mod fault;
+pub mod panic;
 
 // non-board stuff
 pub mod lcd;
 pub mod rotary_encoder;
 
 mod frequency;
+
+// Replaces the default exception vector of `cortex-m-rt`, whose "exceptions"
+// feature is disabled, to hook up the handlers of this crate
+#[cfg(feature = "exceptions")]
+#[allow(dead_code)]
+#[used]
+#[link_section = ".rodata.exceptions"]
+static EXCEPTIONS: cortex_m::exception::Handlers =
+    cortex_m::exception::Handlers {
+        hard_fault: fault::hard_fault,
+        sys_tick: time::sys_tick,
+        ..cortex_m::exception::DEFAULT_HANDLERS
+    };
@@ -0,0 +1,262 @@
+//! Logging with levels, per module filters and ITM channels
+//!
+//! The `error!`, `warn!`, `info!`, `debug!` and `trace!` macros take
+//! `format!` style arguments and log them, with the module they come from,
+//! to the `Sink` chosen with `init`: ITM stimulus ports or the serial port.
+//!
+//! ``` ignore
+//! log::init(Sink::Itm, Some(Level::Info));
+//! log::set_rules(&[
+//!     Rule { module: "app::motor", level: Some(Level::Trace), port: 1 },
+//!     Rule { module: "app::radio", level: None, port: 0 },
+//! ]);
+//!
+//! info!("booted in {} us", time::now().as_micros());
+//! ```
+//!
+//! A record is written if its level is at most the one of the `Rule` for
+//! its module, or the default level if no rule matches. Over ITM it goes to
+//! the rule's stimulus port, so each subsystem can get a channel of its own.
+//!
+//! The `max-level-*` features remove the records above a level at compile
+//! time: their arguments aren't even evaluated. Without any, all the levels
+//! are compiled in.
+//!
+//! NOTE a record is written in a critical section, so records from
+//! different priorities don't get mixed up, but writing one blocks all the
+//! interrupts. Prefer ITM, which is much faster, over the serial port.
+
+use core::fmt::{self, Write};
+
+use cortex_m::interrupt;
+use stm32f100::USART1;
+
+use itm::Port;
+use serial::Serial;
+
+/// How important a record is
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Something failed
+    Error = 1,
+    /// Something may go wrong
+    Warn,
+    /// What's going on
+    Info,
+    /// For debugging
+    Debug,
+    /// For debugging, in detail
+    Trace,
+}
+
+impl Level {
+    /// Upper case name of the level
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Highest level compiled in; `None` if logging is compiled out
+#[cfg(feature = "max-level-off")]
+pub const STATIC_MAX_LEVEL: Option<Level> = None;
+#[cfg(all(feature = "max-level-error", not(feature = "max-level-off")))]
+pub const STATIC_MAX_LEVEL: Option<Level> = Some(Level::Error);
+#[cfg(all(feature = "max-level-warn",
+          not(any(feature = "max-level-off", feature = "max-level-error"))))]
+pub const STATIC_MAX_LEVEL: Option<Level> = Some(Level::Warn);
+#[cfg(all(feature = "max-level-info",
+          not(any(feature = "max-level-off",
+                  feature = "max-level-error",
+                  feature = "max-level-warn"))))]
+pub const STATIC_MAX_LEVEL: Option<Level> = Some(Level::Info);
+#[cfg(all(feature = "max-level-debug",
+          not(any(feature = "max-level-off",
+                  feature = "max-level-error",
+                  feature = "max-level-warn",
+                  feature = "max-level-info"))))]
+pub const STATIC_MAX_LEVEL: Option<Level> = Some(Level::Debug);
+#[cfg(not(any(feature = "max-level-off",
+              feature = "max-level-error",
+              feature = "max-level-warn",
+              feature = "max-level-info",
+              feature = "max-level-debug")))]
+pub const STATIC_MAX_LEVEL: Option<Level> = Some(Level::Trace);
+
+/// Where the records go
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sink {
+    /// Nowhere
+    None,
+    /// ITM stimulus ports, see `itm::init`
+    Itm,
+    /// USART1, see `Serial::init`
+    Serial,
+}
+
+/// Level and stimulus port of the records of a module and its submodules
+#[derive(Clone, Copy)]
+pub struct Rule {
+    /// Module path, e.g. `valuelinediscovery::flash`
+    pub module: &'static str,
+    /// Highest level to write; `None` to write nothing
+    pub level: Option<Level>,
+    /// ITM stimulus port; ignored by the serial sink
+    pub port: u8,
+}
+
+static mut SINK: Sink = Sink::None;
+static mut LEVEL: Option<Level> = Some(Level::Trace);
+static mut RULES: &'static [Rule] = &[];
+
+/// Sends the records to `sink`, filtering them at `level` by default
+pub fn init(sink: Sink, level: Option<Level>) {
+    interrupt::free(|_| unsafe {
+        SINK = sink;
+        LEVEL = level;
+    })
+}
+
+/// Changes the default level, the one of modules without a rule
+pub fn set_level(level: Option<Level>) {
+    interrupt::free(|_| unsafe { LEVEL = level })
+}
+
+/// Replaces the rules; the one with the longest matching module wins
+pub fn set_rules(rules: &'static [Rule]) {
+    interrupt::free(|_| unsafe { RULES = rules })
+}
+
+/// Returns `true` if a record of `level` from `module` would be written
+pub fn enabled(level: Level, module: &str) -> bool {
+    Some(level) <= STATIC_MAX_LEVEL && Some(level) <= filter(module).0
+}
+
+/// Writes a record; use the macros instead
+#[doc(hidden)]
+pub fn log(level: Level, module: &'static str, args: fmt::Arguments) {
+    interrupt::free(|_| {
+        let (max, port) = filter(module);
+        if Some(level) > max {
+            return;
+        }
+
+        match unsafe { SINK } {
+            Sink::None => {}
+            Sink::Itm => {
+                record(&mut Port(port), level, module, args).ok();
+            }
+            Sink::Serial => {
+                // NOTE(unsafe) only writes to the TX buffer, in a critical
+                // section
+                let serial = Serial(unsafe { &*USART1.get() });
+                record(&mut Console(serial), level, module, args).ok();
+            }
+        }
+    })
+}
+
+/// Level and port of the rule for `module`
+fn filter(module: &str) -> (Option<Level>, u8) {
+    let (level, rules) = unsafe { (LEVEL, RULES) };
+
+    let mut best: Option<&Rule> = None;
+    for rule in rules {
+        if matches(rule.module, module) &&
+            best.map(|best| rule.module.len() > best.module.len())
+                .unwrap_or(true)
+        {
+            best = Some(rule);
+        }
+    }
+
+    match best {
+        Some(rule) => (rule.level, rule.port),
+        None => (level, 0),
+    }
+}
+
+/// Returns `true` if `module` is `prefix` or one of its submodules
+fn matches(prefix: &str, module: &str) -> bool {
+    module.starts_with(prefix) &&
+        (module.len() == prefix.len() ||
+             module[prefix.len()..].starts_with("::"))
+}
+
+fn record<W>(
+    w: &mut W,
+    level: Level,
+    module: &str,
+    args: fmt::Arguments,
+) -> fmt::Result
+where
+    W: Write,
+{
+    write!(w, "[{} {}] ", level, module)?;
+    w.write_fmt(args)?;
+    w.write_str("\r\n")
+}
+
+// Blocking writer
+struct Console<'a>(Serial<'a>);
+
+impl<'a> Write for Console<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            while self.0.write(byte).is_err() {}
+        }
+        Ok(())
+    }
+}
+
+/// Logs at `level`, a `log::Level`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {{
+        let level = $level;
+        if Some(level) <= $crate::log::STATIC_MAX_LEVEL {
+            $crate::log::log(level, module_path!(), format_args!($($arg)+));
+        }
+    }}
+}
+
+/// Logs an error
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { log!($crate::log::Level::Error, $($arg)+) }
+}
+
+/// Logs a warning
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { log!($crate::log::Level::Warn, $($arg)+) }
+}
+
+/// Logs information
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { log!($crate::log::Level::Info, $($arg)+) }
+}
+
+/// Logs a debugging message
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { log!($crate::log::Level::Debug, $($arg)+) }
+}
+
+/// Logs a detailed debugging message
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { log!($crate::log::Level::Trace, $($arg)+) }
+}
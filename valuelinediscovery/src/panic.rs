@@ -0,0 +1,328 @@
+//! Panic strategies, chosen with Cargo features
+//!
+//! Without any "panic-*" feature `panic!` is left to `cortex-m-rt`, which
+//! stops at a breakpoint and spins. With one, this crate exports
+//! `rust_begin_unwind`, which replaces the weak `panic_fmt` of
+//! `cortex-m-rt`: it records the location of the `panic!` in the crash
+//! record of `reset`, with the "crash-record" feature, then
+//!
+//! - "panic-halt", the default: stops at a breakpoint if a debugger is
+//!   connected, and spins with the interrupts disabled
+//! - "panic-blink": blinks the line number of the `panic!` with LED 0 of
+//!   `led::LEDS`, one digit after the other, LED 1 marking the end, forever
+//! - "panic-log-reset": logs the message and the location through `log`,
+//!   over ITM or the serial port, whatever `log::init` chose, and resets
+//! - "panic-reset": resets the device right away
+//!
+//! If several are enabled, the first one in that list wins. The halting
+//! ones suit the bench; in the field a reset gets the device going again.
+//! The Makefile builds with `PANIC` and the release builds with
+//! `RELEASE_PANIC`.
+//!
+//! "panic-store" goes with any of them: it also keeps the location and the
+//! message, truncated to `TEXT_SIZE` bytes, in `.noinit` RAM, for
+//! `last_panic` to pick up on the next boot.
+//!
+//! NOTE a `panic!` in the panic handler, e.g. in a `Display` impl of the
+//! message, resets the device without any of this
+
+use core::{cmp, fmt, ptr, str};
+
+use cast::u32;
+
+/// Bytes of the location and the message "panic-store" keeps
+pub const TEXT_SIZE: usize = 160;
+
+// Tag of a valid record
+const MAGIC: u32 = 0xDEAD_2A11;
+
+/// A `panic!` of the previous run
+#[derive(Clone, Copy)]
+pub struct Panic {
+    line: u32,
+    file_len: usize,
+    text: Text,
+}
+
+impl Panic {
+    /// Source file, maybe truncated
+    pub fn file(&self) -> &str {
+        self.text.as_str(0, self.file_len)
+    }
+
+    /// Line
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Formatted message, maybe truncated
+    pub fn message(&self) -> &str {
+        self.text.as_str(self.file_len, self.text.len)
+    }
+
+    /// Returns `true` if the message, or the file name, didn't fit
+    pub fn is_truncated(&self) -> bool {
+        self.text.len == TEXT_SIZE
+    }
+}
+
+impl fmt::Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "panicked at '{}', {}:{}",
+            self.message(),
+            self.file(),
+            self.line
+        )?;
+        if self.is_truncated() {
+            f.write_str(" (truncated)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Text that drops what doesn't fit
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Text {
+    buffer: [u8; TEXT_SIZE],
+    len: usize,
+}
+
+impl Text {
+    fn as_str(&self, start: usize, end: usize) -> &str {
+        str::from_utf8(&self.buffer[start..end]).unwrap_or("?")
+    }
+}
+
+impl fmt::Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // NOTE only whole characters, so the text stays valid UTF-8
+        let mut end = cmp::min(s.len(), TEXT_SIZE - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.buffer[self.len..self.len + end]
+            .copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// The record in `.noinit`
+#[repr(C)]
+struct Record {
+    magic: u32,
+    line: u32,
+    file_len: usize,
+    text: Text,
+    check: u32,
+}
+
+#[link_section = ".noinit"]
+static mut RECORD: Record = Record {
+    magic: 0,
+    line: 0,
+    file_len: 0,
+    text: Text {
+        buffer: [0; TEXT_SIZE],
+        len: 0,
+    },
+    check: 0,
+};
+
+/// Returns the `panic!` that ended the previous run, if any, and clears it
+///
+/// Only "panic-store" keeps it. Call it early: nothing else must have used
+/// the `.noinit` RAM
+pub fn last_panic() -> Option<Panic> {
+    unsafe {
+        let valid = ptr::read_volatile(&RECORD.magic) == MAGIC &&
+            RECORD.check == check(&RECORD) &&
+            RECORD.file_len <= RECORD.text.len &&
+            RECORD.text.len <= TEXT_SIZE;
+        ptr::write_volatile(&mut RECORD.magic, 0);
+
+        if valid {
+            Some(Panic {
+                line: RECORD.line,
+                file_len: RECORD.file_len,
+                text: RECORD.text,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(any(feature = "panic-halt",
+          feature = "panic-blink",
+          feature = "panic-log-reset",
+          feature = "panic-reset",
+          feature = "panic-store"))]
+mod handler {
+    use core::fmt;
+
+    use cortex_m::interrupt;
+    use stm32f100::SCB;
+
+    use reset;
+    #[cfg(feature = "crash-record")]
+    use reset::Crash;
+
+    static mut PANICKING: bool = false;
+
+    // NOTE(allow) not every strategy prints the message
+    #[allow(unused_variables)]
+    #[no_mangle]
+    pub unsafe extern "C" fn rust_begin_unwind(
+        args: fmt::Arguments,
+        file: &'static str,
+        line: u32,
+    ) -> ! {
+        interrupt::disable();
+
+        if PANICKING {
+            reset::system_reset(&*SCB.get())
+        }
+        PANICKING = true;
+
+        #[cfg(feature = "crash-record")]
+        reset::record(Crash::Panic {
+            file: file,
+            line: line,
+        });
+
+        #[cfg(feature = "panic-store")]
+        store(args, file, line);
+
+        match () {
+            #[cfg(any(feature = "panic-halt",
+                      not(any(feature = "panic-blink",
+                              feature = "panic-log-reset",
+                              feature = "panic-reset"))))]
+            () => halt(),
+            #[cfg(all(feature = "panic-blink", not(feature = "panic-halt")))]
+            () => blink(line),
+            #[cfg(all(feature = "panic-log-reset",
+                      not(any(feature = "panic-halt",
+                              feature = "panic-blink"))))]
+            () => log_and_reset(args, file, line),
+            #[cfg(all(feature = "panic-reset",
+                      not(any(feature = "panic-halt",
+                              feature = "panic-blink",
+                              feature = "panic-log-reset"))))]
+            () => reset::system_reset(&*SCB.get()),
+        }
+    }
+
+    /// Keeps the location and the message for `last_panic`
+    #[cfg(feature = "panic-store")]
+    unsafe fn store(args: fmt::Arguments, file: &'static str, line: u32) {
+        use core::fmt::Write;
+        use core::ptr;
+
+        use super::{check, MAGIC, RECORD};
+
+        RECORD.text.len = 0;
+        RECORD.text.write_str(file).ok();
+        RECORD.file_len = RECORD.text.len;
+        RECORD.text.write_fmt(args).ok();
+        RECORD.line = line;
+        RECORD.check = check(&RECORD);
+        ptr::write_volatile(&mut RECORD.magic, MAGIC);
+    }
+
+    #[cfg(any(feature = "panic-halt",
+              not(any(feature = "panic-blink",
+                      feature = "panic-log-reset",
+                      feature = "panic-reset"))))]
+    unsafe fn halt() -> ! {
+        use cortex_m::asm;
+        use stm32f100::DCB;
+
+        // DCB_DHCSR: a debugger is connected
+        const C_DEBUGEN: u32 = 1 << 0;
+
+        // NOTE without a debugger `bkpt` would escalate to HardFault
+        if (*DCB.get()).dhcsr.read() & C_DEBUGEN != 0 {
+            asm::bkpt();
+        }
+
+        loop {}
+    }
+
+    #[cfg(all(feature = "panic-blink", not(feature = "panic-halt")))]
+    unsafe fn blink(line: u32) -> ! {
+        use led::{self, LEDS};
+        use profile;
+        use stm32f100::{DCB, DWT, GPIOC, RCC};
+
+        // Half a period of a blink, in us
+        const BLINK: u32 = 250_000;
+
+        led::init(&*GPIOC.get(), &*RCC.get());
+        profile::init(&*DCB.get(), &*DWT.get());
+        for led in LEDS.iter() {
+            led.off();
+        }
+
+        // least significant first
+        let mut digits = [0; 10];
+        let mut n = 0;
+        let mut rest = line;
+        loop {
+            digits[n] = rest % 10;
+            n += 1;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+
+        loop {
+            for digit in digits[..n].iter().rev() {
+                // NOTE zero is ten blinks; no blink would go unnoticed
+                let blinks = if *digit == 0 { 10 } else { *digit };
+                for _ in 0..blinks {
+                    LEDS[0].on();
+                    profile::delay_us(BLINK);
+                    LEDS[0].off();
+                    profile::delay_us(BLINK);
+                }
+                profile::delay_us(4 * BLINK);
+            }
+
+            LEDS[1].on();
+            profile::delay_us(8 * BLINK);
+            LEDS[1].off();
+            profile::delay_us(4 * BLINK);
+        }
+    }
+
+    #[cfg(all(feature = "panic-log-reset",
+              not(any(feature = "panic-halt", feature = "panic-blink"))))]
+    unsafe fn log_and_reset(
+        args: fmt::Arguments,
+        file: &'static str,
+        line: u32,
+    ) -> ! {
+        error!("panicked at '{}', {}:{}", args, file, line);
+
+        reset::system_reset(&*SCB.get())
+    }
+}
+
+/// Check word of a record
+fn check(record: &Record) -> u32 {
+    let words = [record.line, record.file_len as u32, record.text.len as u32];
+
+    let sum = words
+        .iter()
+        .fold(!MAGIC, |sum, word| sum.rotate_left(1) ^ word);
+    record.text.buffer[..cmp::min(record.text.len, TEXT_SIZE)]
+        .iter()
+        .fold(sum, |sum, byte| sum.rotate_left(1) ^ u32(*byte))
+}
@@ -0,0 +1,327 @@
+//! Low power modes
+//!
+//! Mode     Core   Clocks                      Wake up
+//! Sleep    off    running                     any interrupt / event
+//! Stop     off    off, SRAM and regs kept     any EXTI line (RTC alarm, PVD,
+//!                                             pins)
+//! Standby  off    off, SRAM and regs lost     WKUP pin (PA0), RTC alarm,
+//!                                             NRST, IWDG
+//!
+//! By default the debugger loses the core as soon as it stops its clock (see
+//! `doc/wfi.md`). Call `debug` first to keep the debug port alive in all the
+//! low power modes.
+//!
+//! The programmable voltage detector (PVD) compares VDD against a threshold
+//! and signals crossings through EXTI line 16.
+
+use cortex_m::asm;
+use stm32f100::{Dbg, Exti, Pwr, Rcc, Scb};
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+// SCB_SCR bits
+const SLEEPONEXIT: u32 = 1 << 1;
+const SLEEPDEEP: u32 = 1 << 2;
+
+/// Voltage regulator state during Stop mode
+#[derive(Clone, Copy, PartialEq)]
+pub enum Regulator {
+    /// Regulator on: faster wake up
+    On,
+    /// Regulator in low power mode: lower consumption, slower wake up
+    LowPower,
+}
+
+/// PVD threshold
+#[derive(Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// 2.2 V
+    V2_2,
+    /// 2.3 V
+    V2_3,
+    /// 2.4 V
+    V2_4,
+    /// 2.5 V
+    V2_5,
+    /// 2.6 V
+    V2_6,
+    /// 2.7 V
+    V2_7,
+    /// 2.8 V
+    V2_8,
+    /// 2.9 V
+    V2_9,
+}
+
+impl Threshold {
+    fn bits(self) -> u8 {
+        match self {
+            Threshold::V2_2 => 0b000,
+            Threshold::V2_3 => 0b001,
+            Threshold::V2_4 => 0b010,
+            Threshold::V2_5 => 0b011,
+            Threshold::V2_6 => 0b100,
+            Threshold::V2_7 => 0b101,
+            Threshold::V2_8 => 0b110,
+            Threshold::V2_9 => 0b111,
+        }
+    }
+}
+
+/// Supply crossings the PVD reports
+#[derive(Clone, Copy, PartialEq)]
+pub enum Crossing {
+    /// VDD drops below the threshold
+    Falling,
+    /// VDD rises above the threshold
+    Rising,
+    /// Both
+    Both,
+}
+
+/// Called from `Power::handle_pvd_interrupt` with `true` if VDD is below the
+/// threshold, `false` if it's back above it
+pub type PvdHandler = fn(bool);
+
+static mut PVD_HANDLER: Option<PvdHandler> = None;
+
+/// Keeps the debug port working in Sleep, Stop and Standby modes
+///
+/// NOTE This costs some current, as the clocks needed by the debugger are
+/// kept running. The examples enable it in every build; firmware that's
+/// done with the debugger can leave it disabled.
+pub fn debug(dbg: &Dbg, enabled: bool) {
+    let bit = if enabled { 1 } else { 0 };
+
+    dbg.cr
+        .modify(
+            |_, w| unsafe {
+                w.dbg_sleep()
+                    .bits(bit)
+                    .dbg_stop()
+                    .bits(bit)
+                    .dbg_standby()
+                    .bits(bit)
+            },
+        );
+}
+
+/// Power controller
+///
+/// # Interrupts
+///
+/// Whatever wakes the device up from Sleep or Stop mode runs before the
+/// `sleep`/`stop` call returns.
+#[derive(Clone, Copy)]
+pub struct Power<'a>(pub &'a Pwr);
+
+impl<'a> Power<'a> {
+    /// Initializes the power controller
+    pub fn init(&self, rcc: &Rcc) {
+        // Power up the peripheral
+        rcc.apb1enr.modify(|_, w| w.pwren().enabled());
+    }
+
+    /// Enters Sleep mode until an interrupt arrives
+    pub fn sleep(&self, scb: &Scb) {
+        unsafe { scb.scr.modify(|r| r & !SLEEPDEEP) }
+        asm::wfi();
+    }
+
+    /// Enters Sleep mode until an event (or a pending interrupt, with
+    /// SEVONPEND) arrives
+    pub fn sleep_until_event(&self, scb: &Scb) {
+        unsafe { scb.scr.modify(|r| r & !SLEEPDEEP) }
+        asm::wfe();
+    }
+
+    /// Makes the core go back to sleep every time it returns from an
+    /// interrupt handler to the thread (`idle`)
+    ///
+    /// Useful for fully interrupt driven applications: `idle` only runs until
+    /// the first `sleep`.
+    pub fn sleep_on_exit(&self, scb: &Scb, enabled: bool) {
+        unsafe {
+            scb.scr.modify(
+                |r| if enabled { r | SLEEPONEXIT } else { r & !SLEEPONEXIT },
+            )
+        }
+    }
+
+    /// Enters Stop mode until an EXTI line fires
+    ///
+    /// The device wakes up running from the HSI; the HSE, the PLL and the
+    /// system clock switch are restored to what they were before this call.
+    pub fn stop(&self, scb: &Scb, rcc: &Rcc, regulator: Regulator) {
+        let pwr = self.0;
+
+        let lpds = match regulator {
+            Regulator::On => 0,
+            Regulator::LowPower => 1,
+        };
+
+        // Remember the clock tree
+        let cr = rcc.cr.read();
+        let hseon = cr.hseon().bits();
+        let pllon = cr.pllon().bits();
+        let sw = rcc.cfgr.read().sws().bits();
+
+        pwr.cr
+            .modify(|_, w| unsafe { w.pdds().bits(0).lpds().bits(lpds) });
+        unsafe { scb.scr.modify(|r| r | SLEEPDEEP) }
+        asm::wfi();
+        unsafe { scb.scr.modify(|r| r & !SLEEPDEEP) }
+
+        // Restore the clock tree
+        if hseon == 1 {
+            rcc.cr.modify(|_, w| unsafe { w.hseon().bits(1) });
+            while rcc.cr.read().hserdy().bits() == 0 {}
+        }
+        if pllon == 1 {
+            rcc.cr.modify(|_, w| unsafe { w.pllon().bits(1) });
+            while rcc.cr.read().pllrdy().bits() == 0 {}
+        }
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(sw) });
+        while rcc.cfgr.read().sws().bits() != sw {}
+    }
+
+    /// Enables the WKUP pin (PA0) as a wake up source from Standby mode
+    ///
+    /// A rising edge on PA0 wakes the device up. NOTE PA0 is the user button.
+    pub fn enable_wakeup_pin(&self) {
+        self.0.csr.modify(|_, w| unsafe { w.ewup().bits(1) });
+    }
+
+    /// Disables the WKUP pin, freeing PA0 as a GPIO
+    pub fn disable_wakeup_pin(&self) {
+        self.0.csr.modify(|_, w| unsafe { w.ewup().bits(0) });
+    }
+
+    /// Enters Standby mode
+    ///
+    /// The device wakes up through a reset, caused by the WKUP pin (see
+    /// `enable_wakeup_pin`), an RTC alarm (see `rtc::Rtc::set_alarm`), NRST
+    /// or the IWDG. SRAM and register contents are lost; only the backup
+    /// domain is kept.
+    pub fn standby(&self, scb: &Scb) -> ! {
+        let pwr = self.0;
+
+        // A pending wake up flag would wake the device up right away
+        pwr.cr
+            .modify(|_, w| unsafe { w.pdds().bits(1).cwuf().bits(1) });
+        unsafe { scb.scr.modify(|r| r | SLEEPDEEP) }
+
+        loop {
+            asm::wfi();
+        }
+    }
+
+    /// Returns `true` if the device has been in Standby mode since the last
+    /// `clear_standby_flag`
+    pub fn was_in_standby(&self) -> bool {
+        self.0.csr.read().sbf().bits() == 1
+    }
+
+    /// Clears the standby and wake up flags
+    pub fn clear_standby_flag(&self) {
+        self.0
+            .cr
+            .modify(|_, w| unsafe { w.csbf().bits(1).cwuf().bits(1) });
+    }
+
+    /// Arms the PVD at `threshold`, reporting `crossing`s on EXTI line 16
+    ///
+    /// NOTE The output of the PVD has a 100 mV hysteresis
+    pub fn enable_pvd(
+        &self,
+        exti: &Exti,
+        threshold: Threshold,
+        crossing: Crossing,
+    ) {
+        let pwr = self.0;
+
+        pwr.cr.modify(|_, w| unsafe { w.pls().bits(threshold.bits()) });
+        pwr.cr.modify(|_, w| unsafe { w.pvde().bits(1) });
+
+        // The PVD output goes high when VDD drops below the threshold
+        let (rising, falling) = match crossing {
+            Crossing::Falling => (true, false),
+            Crossing::Rising => (false, true),
+            Crossing::Both => (true, true),
+        };
+        exti.rtsr.modify(|_, w| if rising {
+            w.tr16().enabled()
+        } else {
+            w.tr16().disabled()
+        });
+        exti.ftsr.modify(|_, w| if falling {
+            w.tr16().enabled()
+        } else {
+            w.tr16().disabled()
+        });
+    }
+
+    /// Disarms the PVD
+    pub fn disable_pvd(&self, exti: &Exti) {
+        exti.imr.modify(|_, w| w.mr16().masked());
+        self.0.cr.modify(|_, w| unsafe { w.pvde().bits(0) });
+    }
+
+    /// Returns `true` if VDD is below the PVD threshold
+    pub fn is_below_threshold(&self) -> bool {
+        self.0.csr.read().pvdo().bits() == 1
+    }
+
+    /// Enables the PVD interrupt, calling `handler` from
+    /// `handle_pvd_interrupt`
+    ///
+    /// # Interrupts
+    ///
+    /// - `PvdIrq` - PVD crossing
+    pub fn listen_pvd(&self, exti: &Exti, handler: PvdHandler) {
+        // NOTE(unsafe) the interrupt is disabled until the handler is set
+        unsafe { PVD_HANDLER = Some(handler) }
+        exti.imr.modify(|_, w| w.mr16().enabled());
+    }
+
+    /// Disables the PVD interrupt
+    pub fn unlisten_pvd(&self, exti: &Exti) {
+        exti.imr.modify(|_, w| w.mr16().masked());
+        unsafe { PVD_HANDLER = None }
+    }
+
+    /// Clears the PVD pending flag
+    ///
+    /// Returns `Err` if the PVD hasn't fired
+    pub fn clear_pvd_flag(&self, exti: &Exti) -> Result<()> {
+        if exti.pr.read().pr16().bits() == 0 {
+            Err(Error { _0: () })
+        } else {
+            // NOTE(write) the pending bit is cleared by writing 1
+            exti.pr.write(|w| unsafe { w.pr16().bits(1) });
+            Ok(())
+        }
+    }
+
+    /// Services the PVD; call this from the `PvdIrq` task
+    ///
+    /// If the PVD fired, the flag is cleared and the handler passed to
+    /// `listen_pvd` is called with the state of the supply.
+    ///
+    /// Returns `Err` if the PVD hasn't fired
+    pub fn handle_pvd_interrupt(&self, exti: &Exti) -> Result<()> {
+        self.clear_pvd_flag(exti)?;
+
+        if let Some(handler) = unsafe { PVD_HANDLER } {
+            handler(self.is_below_threshold());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Cycle accurate profiling and delays with the DWT cycle counter
+//!
+//! `init` starts `DWT_CYCCNT`, a 32-bit counter of core clock cycles: it
+//! wraps around every 536 s at 8 MHz, so it measures anything shorter than
+//! that. A `Probe` keeps the statistics of many measurements of the same
+//! code, e.g. an interrupt handler; `report` prints them over anything that
+//! implements `fmt::Write`, like the serial port or an ITM stimulus port.
+//!
+//! The delays count cycles too: one cycle is 125 ns at 8 MHz.
+//!
+//! NOTE the cycle counter doesn't count while the core sleeps (`wfi`)
+
+use core::{cmp, fmt};
+
+use cast::{u32, u64};
+use stm32f100::{Dcb, Dwt, DWT};
+
+use frequency;
+
+/// Frequency, in Hz, of the cycle counter: the core clock
+pub const CLOCK: u32 = frequency::AHB;
+
+// DCB_DEMCR: enables the DWT and ITM
+const TRCENA: u32 = 1 << 24;
+
+// DWT_CTRL: enables CYCCNT
+const CYCCNTENA: u32 = 1 << 0;
+
+/// Starts the cycle counter
+///
+/// NOTE a debugger may also start and stop it
+pub fn init(dcb: &Dcb, dwt: &Dwt) {
+    unsafe {
+        dcb.demcr.modify(|r| r | TRCENA);
+        dwt.cyccnt.write(0);
+        dwt.ctrl.modify(|r| r | CYCCNTENA);
+    }
+}
+
+/// Returns the cycle counter
+pub fn cycles() -> u32 {
+    // NOTE(unsafe) atomic read
+    unsafe { (*DWT.get()).cyccnt.read() }
+}
+
+/// Converts `cycles` to microseconds, rounding down
+pub fn cycles_to_us(cycles: u32) -> u32 {
+    cycles / (CLOCK / 1_000_000)
+}
+
+/// Converts `cycles` to nanoseconds, rounding down
+pub fn cycles_to_ns(cycles: u32) -> u64 {
+    u64(cycles) * 1_000_000_000 / u64(CLOCK)
+}
+
+/// Waits for at least `cycles` core clock cycles
+///
+/// A call costs a few cycles of its own, so very short delays are longer
+/// than asked. NOTE returns right away if the counter isn't running, see
+/// `init`
+#[inline(always)]
+pub fn delay_cycles(cycles: u32) {
+    let dwt = unsafe { &*DWT.get() };
+
+    if dwt.ctrl.read() & CYCCNTENA == 0 {
+        return;
+    }
+
+    let start = dwt.cyccnt.read();
+    while dwt.cyccnt.read().wrapping_sub(start) < cycles {}
+}
+
+/// Waits for at least `ns` nanoseconds, rounded up to whole cycles
+#[inline(always)]
+pub fn delay_ns(ns: u32) {
+    let cycles = (u64(ns) * u64(CLOCK) + 999_999_999) / 1_000_000_000;
+    delay_cycles(u32(cycles).unwrap_or(u32::max_value()));
+}
+
+/// Waits for at least `us` microseconds
+#[inline(always)]
+pub fn delay_us(us: u32) {
+    delay_cycles(us.saturating_mul(CLOCK / 1_000_000));
+}
+
+/// A running measurement
+#[derive(Clone, Copy)]
+pub struct Stopwatch {
+    start: u32,
+}
+
+impl Stopwatch {
+    /// Starts measuring
+    #[inline(always)]
+    pub fn start() -> Stopwatch {
+        Stopwatch { start: cycles() }
+    }
+
+    /// Cycles since `start`
+    #[inline(always)]
+    pub fn cycles(&self) -> u32 {
+        cycles().wrapping_sub(self.start)
+    }
+}
+
+/// Statistics of the measurements of a piece of code
+///
+/// Put it in a `static mut` to collect measurements from an interrupt
+/// handler, and read it where the handler can't preempt.
+#[derive(Clone, Copy)]
+pub struct Probe {
+    name: &'static str,
+    count: u32,
+    min: u32,
+    max: u32,
+    total: u64,
+}
+
+impl Probe {
+    /// A probe without measurements
+    pub const fn new(name: &'static str) -> Probe {
+        Probe {
+            name: name,
+            count: 0,
+            min: u32::max_value(),
+            max: 0,
+            total: 0,
+        }
+    }
+
+    /// Adds a measurement of `cycles` cycles
+    pub fn record(&mut self, cycles: u32) {
+        self.count = self.count.saturating_add(1);
+        self.min = cmp::min(self.min, cycles);
+        self.max = cmp::max(self.max, cycles);
+        self.total = self.total.saturating_add(u64(cycles));
+    }
+
+    /// Runs `f` and records how long it took
+    ///
+    /// NOTE the measurement includes a few cycles of overhead
+    pub fn measure<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let stopwatch = Stopwatch::start();
+        let result = f();
+        self.record(stopwatch.cycles());
+        result
+    }
+
+    /// Forgets all the measurements
+    pub fn reset(&mut self) {
+        *self = Probe::new(self.name);
+    }
+
+    /// Name of the probe
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Number of measurements
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Shortest measurement, in cycles; `None` without measurements
+    pub fn min(&self) -> Option<u32> {
+        if self.count == 0 { None } else { Some(self.min) }
+    }
+
+    /// Longest measurement, in cycles; `None` without measurements
+    pub fn max(&self) -> Option<u32> {
+        if self.count == 0 { None } else { Some(self.max) }
+    }
+
+    /// Average measurement, in cycles; `None` without measurements
+    pub fn average(&self) -> Option<u32> {
+        if self.count == 0 {
+            None
+        } else {
+            u32(self.total / u64(self.count)).ok()
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.min(), self.average(), self.max()) {
+            (Some(min), Some(average), Some(max)) => {
+                write!(
+                    f,
+                    "{}: {} runs, min {} / avg {} / max {} cycles \
+                     ({} / {} / {} us)",
+                    self.name,
+                    self.count,
+                    min,
+                    average,
+                    max,
+                    cycles_to_us(min),
+                    cycles_to_us(average),
+                    cycles_to_us(max)
+                )
+            }
+            _ => write!(f, "{}: no runs", self.name),
+        }
+    }
+}
+
+/// Writes the statistics of `probes` to `w`, one line per probe
+pub fn report<W>(w: &mut W, probes: &[&Probe]) -> fmt::Result
+where
+    W: fmt::Write,
+{
+    for probe in probes {
+        write!(w, "{}\r\n", probe)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! Reset cause and crash records
+//!
+//! `reset_cause` tells why the device last rebooted; `system_reset`
+//! reboots it. With the "crash-record" feature, fault and panic handlers
+//! leave a `Crash` record in the backup registers with `record`; the next
+//! boot picks it up with `last_crash`.
+//!
+//! The crash record uses the last `RECORD_SIZE` backup registers (DR5-DR10
+//! on medium density parts), the ones `backup::RESERVED` keeps out of
+//! `Backup::store`. Don't write those with `Backup::write` either. Without
+//! the feature no register is reserved.
+
+use core::fmt;
+#[cfg(feature = "crash-record")]
+use core::{slice, str};
+
+#[cfg(feature = "crash-record")]
+use cast::{u16, u32};
+use stm32f100::{Rcc, Scb};
+#[cfg(feature = "crash-record")]
+use stm32f100::{self, Bkp};
+
+#[cfg(feature = "crash-record")]
+use backup::{self, Backup};
+
+/// Number of backup registers the crash record takes
+#[cfg(feature = "crash-record")]
+pub const RECORD_SIZE: usize = backup::RESERVED;
+
+/// Index of the first backup register of the crash record
+#[cfg(feature = "crash-record")]
+pub const RECORD: usize = backup::REGISTERS - RECORD_SIZE;
+
+// SCB_AIRCR
+const VECTKEY: u32 = 0x05FA << 16;
+const PRIGROUP: u32 = 0b111 << 8;
+const SYSRESETREQ: u32 = 1 << 2;
+
+// Tags of the first register of the record
+#[cfg(feature = "crash-record")]
+const PANIC: u16 = 0xDEA1;
+#[cfg(feature = "crash-record")]
+const HARD_FAULT: u16 = 0xDEA2;
+
+// Where `&'static str`s live
+#[cfg(feature = "crash-record")]
+const FLASH_START: u32 = 0x0800_0000;
+#[cfg(feature = "crash-record")]
+const FLASH_END: u32 = 0x0808_0000;
+
+/// Cause of the last reset
+#[derive(Clone, Copy, PartialEq)]
+pub enum Cause {
+    /// Power on or brown out
+    PowerOn,
+    /// The NRST pin was pulled low, e.g. by the reset button or the debugger
+    Pin,
+    /// `SCB_AIRCR.SYSRESETREQ`, e.g. after flashing
+    Software,
+    /// The independent watchdog expired
+    IndependentWatchdog,
+    /// The window watchdog expired or was refreshed too early
+    WindowWatchdog,
+    /// Entering Standby or Stop mode while the option bytes forbid it
+    LowPower,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Cause::PowerOn => "power on",
+            Cause::Pin => "reset pin",
+            Cause::Software => "software reset",
+            Cause::IndependentWatchdog => "independent watchdog",
+            Cause::WindowWatchdog => "window watchdog",
+            Cause::LowPower => "low power",
+        })
+    }
+}
+
+/// Returns the cause of the last reset and clears the reset flags
+///
+/// Call this once, early in `init`. The flags accumulate over resets until
+/// cleared, and every reset also sets the pin flag (internal resets drive
+/// NRST low), so the most specific flag wins.
+pub fn reset_cause(rcc: &Rcc) -> Cause {
+    let csr = rcc.csr.read();
+
+    let cause = if csr.lpwrrstf().bits() == 1 {
+        Cause::LowPower
+    } else if csr.wwdgrstf().bits() == 1 {
+        Cause::WindowWatchdog
+    } else if csr.iwdgrstf().bits() == 1 {
+        Cause::IndependentWatchdog
+    } else if csr.sftrstf().bits() == 1 {
+        Cause::Software
+    } else if csr.porrstf().bits() == 1 {
+        Cause::PowerOn
+    } else {
+        Cause::Pin
+    };
+
+    // NOTE(write) the reset flags are cleared by writing 1 to RMVF
+    rcc.csr.modify(|_, w| unsafe { w.rmvf().bits(1) });
+
+    cause
+}
+
+/// Resets the whole device, like the reset button does
+///
+/// The next `reset_cause` returns `Cause::Software`
+pub fn system_reset(scb: &Scb) -> ! {
+    unsafe { scb.aircr.modify(|r| VECTKEY | r & PRIGROUP | SYSRESETREQ) }
+
+    // the reset takes a few cycles
+    loop {}
+}
+
+/// What ended the previous run
+#[derive(Clone, Copy, PartialEq)]
+pub enum Crash {
+    /// `panic!` at `file`:`line`
+    Panic {
+        /// Source file
+        file: &'static str,
+        /// Line, saturated at 65535
+        line: u32,
+    },
+    /// HardFault with the stacked program counter `pc`
+    HardFault {
+        /// Address of the faulting instruction
+        pc: u32,
+    },
+}
+
+impl fmt::Display for Crash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Crash::Panic { file, line } => {
+                write!(f, "panicked at {}:{}", file, line)
+            }
+            Crash::HardFault { pc } => write!(f, "HardFault at {:#010x}", pc),
+        }
+    }
+}
+
+/// Records `crash` in the backup registers
+///
+/// Meant for panic and fault handlers: it doesn't need any peripheral
+/// token and it unlocks the backup domain by itself.
+#[cfg(feature = "crash-record")]
+pub fn record(crash: Crash) {
+    // NOTE(unsafe) the previous run is over; nothing else is running
+    let bkp = unsafe { &*stm32f100::BKP.get() };
+    let pwr = unsafe { &*stm32f100::PWR.get() };
+    let rcc = unsafe { &*stm32f100::RCC.get() };
+
+    let (tag, address, len, line) = match crash {
+        Crash::Panic { file, line } => {
+            (
+                PANIC,
+                file.as_ptr() as u32,
+                u16(file.len()).unwrap_or(0),
+                u16(line).unwrap_or(0xFFFF),
+            )
+        }
+        Crash::HardFault { pc } => (HARD_FAULT, pc, 0, 0),
+    };
+
+    let words = [tag, address as u16, (address >> 16) as u16, len, line];
+
+    backup::unlock(pwr, rcc);
+    let backup = Backup(bkp);
+    for (i, word) in words.iter().enumerate() {
+        backup.write(RECORD + i, *word).ok();
+    }
+    backup.write(RECORD + words.len(), check(&words)).ok();
+}
+
+/// Returns the crash recorded by the previous run, if any, and clears it
+///
+/// NOTE the backup domain must be unlocked (see `backup::unlock`). The file
+/// name of a panic points into the flash, so it's only meaningful if the
+/// firmware wasn't replaced in between.
+#[cfg(feature = "crash-record")]
+pub fn last_crash(bkp: &Bkp) -> Option<Crash> {
+    let backup = Backup(bkp);
+
+    let mut words = [0; RECORD_SIZE];
+    for (i, word) in words.iter_mut().enumerate() {
+        match backup.read(RECORD + i) {
+            Ok(value) => *word = value,
+            Err(_) => return None,
+        }
+    }
+    for i in 0..RECORD_SIZE {
+        backup.write(RECORD + i, 0).ok();
+    }
+
+    let (words, sum) = words.split_at(RECORD_SIZE - 1);
+    if check(words) != sum[0] {
+        return None;
+    }
+
+    let address = u32(words[1]) | u32(words[2]) << 16;
+    match words[0] {
+        PANIC => {
+            let len = u32(words[3]);
+            if address < FLASH_START || address > FLASH_END ||
+                address + len > FLASH_END
+            {
+                return None;
+            }
+
+            let bytes = unsafe {
+                slice::from_raw_parts(address as *const u8, len as usize)
+            };
+            Some(Crash::Panic {
+                file: str::from_utf8(bytes).unwrap_or("?"),
+                line: u32(words[4]),
+            })
+        }
+        HARD_FAULT => Some(Crash::HardFault { pc: address }),
+        _ => None,
+    }
+}
+
+/// Check word of the crash record
+///
+/// NOTE seeded so that a cleared record (all zeros) doesn't check out
+#[cfg(feature = "crash-record")]
+fn check(words: &[u16]) -> u16 {
+    words.iter().fold(0xFFFF, |sum, word| sum.rotate_left(1) ^ word)
+}
@@ -0,0 +1,430 @@
+//! Real time clock and calendar
+//!
+//! The RTC is a 32-bit seconds counter in the backup domain. It keeps
+//! counting through resets and, with a battery on VBAT, through power loss.
+//! The counter holds the number of seconds since 1970-01-01 00:00:00 UTC.
+//!
+//! NOTE The Discovery board ships without the 32.768 kHz crystal (X3), so
+//! `Clock::Lse` needs one fitted. `Clock::Lsi` always works but the LSI is
+//! only accurate to about +/-50%, see `Rtc::calibrate_lsi`.
+
+use cast::{u16, u32, u8};
+use stm32f100::{self, Dcb, Dwt, Exti, Pwr, Rcc};
+
+use backup;
+use frequency;
+use profile;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// Nominal frequency of the LSE crystal, in Hz
+pub const LSE: u32 = 32_768;
+
+/// Nominal frequency of the LSI oscillator, in Hz
+pub const LSI: u32 = 40_000;
+
+// Iterations to wait for the LSE to start, ~1 s at 8 MHz
+const LSE_TIMEOUT: u32 = 1_000_000;
+
+/// RTC clock source
+#[derive(Clone, Copy, PartialEq)]
+pub enum Clock {
+    /// 32.768 kHz external crystal
+    Lse,
+    /// ~40 kHz internal RC oscillator
+    Lsi,
+}
+
+impl Clock {
+    fn rtcsel(self) -> u8 {
+        match self {
+            Clock::Lse => 0b01,
+            Clock::Lsi => 0b10,
+        }
+    }
+}
+
+/// RTC events
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Once per second
+    Second,
+    /// The counter reached the alarm value
+    Alarm,
+    /// The counter wrapped around
+    Overflow,
+}
+
+/// Calendar date and time, UTC
+#[derive(Clone, Copy, PartialEq)]
+pub struct DateTime {
+    /// Year, 1970-2106
+    pub year: u16,
+    /// Month, 1-12
+    pub month: u8,
+    /// Day of the month, 1-31
+    pub day: u8,
+    /// Hour, 0-23
+    pub hour: u8,
+    /// Minute, 0-59
+    pub minute: u8,
+    /// Second, 0-59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Converts seconds since 1970-01-01 00:00:00 into a calendar date
+    pub fn from_timestamp(timestamp: u32) -> DateTime {
+        let days = timestamp / 86_400;
+        let seconds = timestamp % 86_400;
+
+        // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: u16(year).unwrap(),
+            month: u8(month).unwrap(),
+            day: u8(day).unwrap(),
+            hour: u8(seconds / 3_600).unwrap(),
+            minute: u8(seconds / 60 % 60).unwrap(),
+            second: u8(seconds % 60).unwrap(),
+        }
+    }
+
+    /// Converts the date into seconds since 1970-01-01 00:00:00
+    ///
+    /// Returns `Err` if the date is invalid or out of range
+    pub fn timestamp(&self) -> Result<u32> {
+        if self.year < 1970 || self.year > 2106 || self.month == 0 ||
+            self.month > 12 || self.day == 0 ||
+            self.day > days_in_month(self.year, self.month) ||
+            self.hour > 23 || self.minute > 59 || self.second > 59
+        {
+            return Err(Error { _0: () });
+        }
+
+        // Civil date to days, see `from_timestamp`
+        let month = u32(self.month);
+        let year = u32(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + u32(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        let seconds = u32(self.hour) * 3_600 + u32(self.minute) * 60 +
+            u32(self.second);
+
+        days.checked_mul(86_400)
+            .and_then(|s| s.checked_add(seconds))
+            .ok_or(Error { _0: () })
+    }
+
+    /// Day of the week, 0 = Monday ... 6 = Sunday
+    pub fn weekday(&self) -> u8 {
+        let days = self.timestamp().unwrap_or(0) / 86_400;
+
+        // 1970-01-01 was a Thursday
+        u8((days + 3) % 7).unwrap()
+    }
+}
+
+/// Returns `true` if `year` is a leap year
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days in `month` (1-12) of `year`
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 => if is_leap_year(year) { 29 } else { 28 },
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Real time clock
+///
+/// # Interrupts
+///
+/// - `RtcWkupIrq` - RTC global interrupt: second, overflow (and alarm)
+/// - `RtcalarmIrq` - alarm, through EXTI line 17
+#[derive(Clone, Copy)]
+pub struct Rtc<'a>(pub &'a stm32f100::Rtc);
+
+impl<'a> Rtc<'a> {
+    /// Initializes the RTC to count seconds from `clock`
+    ///
+    /// If the RTC is already running from `clock` (e.g. after a reset) it is
+    /// left alone, so the time is kept. Switching to a different clock
+    /// resets the backup domain, which also clears the backup registers.
+    ///
+    /// Returns `true` if the RTC was already running, or `Err` if the LSE
+    /// failed to start
+    pub fn init(&self, pwr: &Pwr, rcc: &Rcc, clock: Clock) -> Result<bool> {
+        let rtc = self.0;
+
+        backup::unlock(pwr, rcc);
+
+        if clock == Clock::Lsi {
+            // NOTE the LSI is not part of the backup domain, it stops on reset
+            rcc.csr.modify(|_, w| unsafe { w.lsion().bits(1) });
+            while rcc.csr.read().lsirdy().bits() == 0 {}
+        }
+
+        let bdcr = rcc.bdcr.read();
+        let running = bdcr.rtcen().bits() == 1;
+        if running && bdcr.rtcsel().bits() == clock.rtcsel() {
+            self.wait_for_sync();
+            return Ok(true);
+        }
+
+        if bdcr.rtcsel().bits() != 0 {
+            // RTCSEL can only be changed by a backup domain reset
+            rcc.bdcr.modify(|_, w| unsafe { w.bdrst().bits(1) });
+            rcc.bdcr.modify(|_, w| unsafe { w.bdrst().bits(0) });
+        }
+
+        let prescaler = match clock {
+            Clock::Lse => {
+                rcc.bdcr.modify(|_, w| unsafe { w.lseon().bits(1) });
+
+                let mut timeout = LSE_TIMEOUT;
+                while rcc.bdcr.read().lserdy().bits() == 0 {
+                    timeout -= 1;
+                    if timeout == 0 {
+                        rcc.bdcr.modify(|_, w| unsafe { w.lseon().bits(0) });
+                        return Err(Error { _0: () });
+                    }
+                }
+
+                LSE
+            }
+            Clock::Lsi => LSI,
+        };
+
+        rcc.bdcr
+            .modify(|_, w| unsafe {
+                w.rtcsel().bits(clock.rtcsel()).rtcen().bits(1)
+            });
+
+        self.wait_for_sync();
+        self.set_prescaler(prescaler);
+        self.configure(|rtc| {
+            rtc.cnth.write(|w| unsafe { w.cnth().bits(0) });
+            rtc.cntl.write(|w| unsafe { w.cntl().bits(0) });
+        });
+
+        Ok(false)
+    }
+
+    /// Sets the RTC clock divider, so `frequency` RTC clock cycles make up
+    /// one second
+    pub fn set_prescaler(&self, frequency: u32) {
+        let prl = frequency - 1;
+
+        self.configure(|rtc| {
+            rtc.prlh.write(|w| unsafe { w.prlh().bits(u8(prl >> 16).unwrap()) });
+            rtc.prll
+                .write(|w| unsafe { w.prll().bits(u16(prl & 0xFFFF).unwrap()) });
+        });
+    }
+
+    /// Returns the number of seconds since 1970-01-01 00:00:00
+    pub fn now(&self) -> u32 {
+        let rtc = self.0;
+
+        // NOTE the high half may change between the two reads
+        loop {
+            let high = rtc.cnth.read().cnth().bits();
+            let low = rtc.cntl.read().cntl().bits();
+
+            if high == rtc.cnth.read().cnth().bits() {
+                return u32(high) << 16 | u32(low);
+            }
+        }
+    }
+
+    /// Sets the number of seconds since 1970-01-01 00:00:00
+    pub fn set(&self, timestamp: u32) {
+        self.configure(|rtc| {
+            rtc.cnth
+                .write(|w| unsafe { w.cnth().bits(u16(timestamp >> 16).unwrap()) });
+            rtc.cntl
+                .write(|w| unsafe { w.cntl().bits(u16(timestamp & 0xFFFF).unwrap()) });
+        });
+    }
+
+    /// Returns the current date and time
+    pub fn datetime(&self) -> DateTime {
+        DateTime::from_timestamp(self.now())
+    }
+
+    /// Sets the current date and time
+    ///
+    /// Returns `Err` if the date is invalid
+    pub fn set_datetime(&self, datetime: &DateTime) -> Result<()> {
+        let timestamp = datetime.timestamp()?;
+        self.set(timestamp);
+        Ok(())
+    }
+
+    /// Sets the alarm to go off when the counter reaches `timestamp`
+    pub fn set_alarm(&self, timestamp: u32) {
+        self.configure(|rtc| {
+            rtc.alrh
+                .write(|w| unsafe { w.alrh().bits(u16(timestamp >> 16).unwrap()) });
+            rtc.alrl
+                .write(|w| unsafe { w.alrl().bits(u16(timestamp & 0xFFFF).unwrap()) });
+        });
+    }
+
+    /// Enables the interrupt of `event`
+    ///
+    /// The alarm also raises `RtcalarmIrq` through EXTI line 17, which can
+    /// wake the device from Stop mode.
+    pub fn listen(&self, exti: &Exti, event: Event) {
+        match event {
+            Event::Second => {
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.secie().bits(1) })
+                })
+            }
+            Event::Overflow => {
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.owie().bits(1) })
+                })
+            }
+            Event::Alarm => {
+                exti.rtsr.modify(|_, w| w.tr17().enabled());
+                exti.imr.modify(|_, w| w.mr17().enabled());
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.alrie().bits(1) })
+                })
+            }
+        }
+    }
+
+    /// Disables the interrupt of `event`
+    pub fn unlisten(&self, exti: &Exti, event: Event) {
+        match event {
+            Event::Second => {
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.secie().bits(0) })
+                })
+            }
+            Event::Overflow => {
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.owie().bits(0) })
+                })
+            }
+            Event::Alarm => {
+                exti.imr.modify(|_, w| w.mr17().masked());
+                self.configure(|rtc| {
+                    rtc.crh.modify(|_, w| unsafe { w.alrie().bits(0) })
+                })
+            }
+        }
+    }
+
+    /// Clears the flag of `event`
+    ///
+    /// Returns `Err` if the event hasn't occurred
+    pub fn clear_flag(&self, exti: &Exti, event: Event) -> Result<()> {
+        let rtc = self.0;
+        let crl = rtc.crl.read();
+
+        let set = match event {
+            Event::Second => crl.secf().bits(),
+            Event::Alarm => crl.alrf().bits(),
+            Event::Overflow => crl.owf().bits(),
+        };
+        if set == 0 {
+            return Err(Error { _0: () });
+        }
+
+        match event {
+            Event::Second => rtc.crl.modify(|_, w| unsafe { w.secf().bits(0) }),
+            Event::Overflow => rtc.crl.modify(|_, w| unsafe { w.owf().bits(0) }),
+            Event::Alarm => {
+                rtc.crl.modify(|_, w| unsafe { w.alrf().bits(0) });
+                // NOTE(write) the pending bit is cleared by writing 1
+                exti.pr.write(|w| unsafe { w.pr17().bits(1) });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Measures the LSI against the core clock and trims the prescaler so
+    /// the RTC counts real seconds
+    ///
+    /// The measurement counts core cycles with the DWT cycle counter, which
+    /// it starts (see `profile::init`), so SysTick and `time` are left
+    /// alone. Takes about two seconds. Returns the measured LSI frequency,
+    /// in Hz.
+    ///
+    /// NOTE Only meaningful when running from `Clock::Lsi`
+    pub fn calibrate_lsi(&self, dcb: &Dcb, dwt: &Dwt) -> u32 {
+        let rtc = self.0;
+
+        // The RTC "second" lasts LSI cycles at the nominal frequency
+        self.set_prescaler(LSI);
+        profile::init(dcb, dwt);
+
+        // Align with the start of an RTC second
+        rtc.crl.modify(|_, w| unsafe { w.secf().bits(0) });
+        while rtc.crl.read().secf().bits() == 0 {}
+        rtc.crl.modify(|_, w| unsafe { w.secf().bits(0) });
+
+        let start = profile::cycles();
+        while rtc.crl.read().secf().bits() == 0 {}
+        let cycles = profile::cycles().wrapping_sub(start);
+
+        // LSI = LSI cycles per RTC second / RTC second in core seconds
+        let lsi = (LSI as u64 * frequency::AHB as u64 / cycles as u64) as u32;
+        self.set_prescaler(lsi);
+
+        lsi
+    }
+
+    /// Waits until the registers are synchronized with the RTC clock
+    ///
+    /// Needed after a reset or after the APB1 clock was stopped, before
+    /// reading the counter
+    fn wait_for_sync(&self) {
+        let rtc = self.0;
+
+        rtc.crl.modify(|_, w| unsafe { w.rsf().bits(0) });
+        while rtc.crl.read().rsf().bits() == 0 {}
+    }
+
+    /// Runs `f` with the RTC in configuration mode
+    fn configure<F>(&self, f: F)
+    where
+        F: FnOnce(&stm32f100::Rtc),
+    {
+        let rtc = self.0;
+
+        while rtc.crl.read().rtoff().bits() == 0 {}
+        rtc.crl.modify(|_, w| unsafe { w.cnf().bits(1) });
+        f(rtc);
+        rtc.crl.modify(|_, w| unsafe { w.cnf().bits(0) });
+        while rtc.crl.read().rtoff().bits() == 0 {}
+    }
+}
@@ -0,0 +1,148 @@
+//! Device electronic signature and identification
+//!
+//! The factory programs a 96-bit unique ID and the size of the flash in the
+//! system memory. The device and its silicon revision are in
+//! `DBGMCU_IDCODE`.
+//!
+//! NOTE on the F100 `DBGMCU_IDCODE` reads as zero unless a debugger is
+//! connected (see the errata sheet), so `id_code` returns `None` then. Use
+//! `flash_size`, which always works, to tell medium and high density parts
+//! apart.
+
+use core::{fmt, ptr};
+
+use cast::usize;
+use stm32f100::Dbg;
+
+use crc::software;
+
+// Unique device ID, 3 words
+const U_ID: u32 = 0x1FFF_F7E8;
+
+// Flash size, in KiB
+const F_SIZE: u32 = 0x1FFF_F7E0;
+
+/// 96-bit unique device ID, lowest word first
+#[derive(Clone, Copy, PartialEq)]
+pub struct Uid(pub [u32; 3]);
+
+impl Uid {
+    /// Serial number: the ID as 24 hex digits, highest word first
+    pub fn serial_number(&self) -> [u8; 24] {
+        const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+        let mut digits = [0; 24];
+        for (i, word) in self.0.iter().rev().enumerate() {
+            for j in 0..8 {
+                let nibble = (word >> (28 - 4 * j)) & 0xF;
+                digits[8 * i + j] = DIGITS[usize(nibble)];
+            }
+        }
+
+        digits
+    }
+
+    /// MAC-48 address derived from the ID
+    ///
+    /// The address is locally administered and unicast, and the same on
+    /// every boot. Different devices get different addresses, except for
+    /// the odd collision: it's a hash of the 96 bits.
+    pub fn mac_address(&self) -> [u8; 6] {
+        let uid = self.0;
+        let high = software::checksum(&uid);
+        let low = software::checksum(&[uid[2], uid[1], uid[0]]);
+
+        [
+            0x02,
+            (high >> 24) as u8,
+            (high >> 16) as u8,
+            (high >> 8) as u8,
+            high as u8,
+            low as u8,
+        ]
+    }
+}
+
+impl fmt::Display for Uid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let uid = self.0;
+        write!(f, "{:08X}{:08X}{:08X}", uid[2], uid[1], uid[0])
+    }
+}
+
+/// A device, as identified by `DBGMCU_IDCODE`
+#[derive(Clone, Copy, PartialEq)]
+pub enum Device {
+    /// Low and medium density value line (STM32F100x4 - STM32F100xB)
+    ValueLine,
+    /// High density value line (STM32F100xC - STM32F100xE)
+    ValueLineHighDensity,
+    /// Something else
+    Other(u16),
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Device::ValueLine => f.write_str("STM32F100 low/medium density"),
+            Device::ValueLineHighDensity => {
+                f.write_str("STM32F100 high density")
+            }
+            Device::Other(id) => write!(f, "unknown device {:#05x}", id),
+        }
+    }
+}
+
+/// Contents of `DBGMCU_IDCODE`
+#[derive(Clone, Copy, PartialEq)]
+pub struct IdCode {
+    /// The device
+    pub device: Device,
+    /// Silicon revision, e.g. 0x1000 for revision A
+    pub revision: u16,
+}
+
+/// Returns the unique device ID
+pub fn uid() -> Uid {
+    // NOTE(unsafe) read only registers set by the factory
+    unsafe {
+        Uid(
+            [
+                ptr::read_volatile(U_ID as *const u32),
+                ptr::read_volatile((U_ID + 4) as *const u32),
+                ptr::read_volatile((U_ID + 8) as *const u32),
+            ],
+        )
+    }
+}
+
+/// Returns the size of the flash, in KiB
+pub fn flash_size() -> u16 {
+    // NOTE(unsafe) read only register set by the factory
+    unsafe { ptr::read_volatile(F_SIZE as *const u16) }
+}
+
+/// Returns `true` on high density parts: more than 128 KiB of flash
+pub fn is_high_density() -> bool {
+    flash_size() > 128
+}
+
+/// Returns the device and its revision
+///
+/// Returns `None` if `DBGMCU_IDCODE` can't be read, i.e. when no debugger
+/// is connected
+pub fn id_code(dbg: &Dbg) -> Option<IdCode> {
+    let idcode = dbg.idcode.read();
+
+    let device = match idcode.dev_id().bits() {
+        0 => return None,
+        0x420 => Device::ValueLine,
+        0x428 => Device::ValueLineHighDensity,
+        id => Device::Other(id),
+    };
+
+    Some(IdCode {
+        device: device,
+        revision: idcode.rev_id().bits(),
+    })
+}
@@ -0,0 +1,220 @@
+//! Software timers: many periodic and one-shot timers on one interrupt
+//!
+//! The timers live in a static table of `CAPACITY` entries and count ticks
+//! of whatever calls `tick`: the task of a hardware timer, or SysTick with
+//! `time::listen(soft_timer::tick)`. Their periods are in those ticks.
+//!
+//! An expiring timer sets its flag, which `SoftTimer::clear_flag` polls,
+//! and calls its callback, if it has one. Callbacks run in the context of
+//! `tick`, so keep them short.
+
+use cortex_m::interrupt;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// Number of timers that can be created
+pub const CAPACITY: usize = 16;
+
+/// Called by `tick` when the timer expires
+///
+/// It gets the timer by reference: it can restart or stop it, but not
+/// delete it.
+pub type Callback = fn(&SoftTimer);
+
+/// What happens when a timer expires
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The timer stops
+    OneShot,
+    /// The timer starts over
+    Periodic,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    used: bool,
+    running: bool,
+    expired: bool,
+    mode: Mode,
+    period: u32,
+    // Value of `TICKS` at which the timer expires
+    deadline: u32,
+    callback: Option<Callback>,
+}
+
+const UNUSED: Entry = Entry {
+    used: false,
+    running: false,
+    expired: false,
+    mode: Mode::OneShot,
+    period: 0,
+    deadline: 0,
+    callback: None,
+};
+
+static mut TABLE: [Entry; CAPACITY] = [UNUSED; CAPACITY];
+
+// Ticks counted by `tick`
+static mut TICKS: u32 = 0;
+
+/// A software timer
+///
+/// NOTE(!Copy) `delete` consumes the only handle, so none is left to
+/// control the timer that reuses the entry
+#[derive(PartialEq)]
+pub struct SoftTimer(usize);
+
+impl SoftTimer {
+    /// Creates a stopped timer that expires `period` ticks after it's
+    /// started
+    ///
+    /// Returns `Err` if `period` is zero or all the timers are taken
+    pub fn new(
+        mode: Mode,
+        period: u32,
+        callback: Option<Callback>,
+    ) -> Result<SoftTimer> {
+        if period == 0 {
+            return Err(Error { _0: () });
+        }
+
+        interrupt::free(|_| unsafe {
+            match TABLE.iter().position(|entry| !entry.used) {
+                Some(i) => {
+                    TABLE[i] = Entry {
+                        used: true,
+                        mode: mode,
+                        period: period,
+                        callback: callback,
+                        ..UNUSED
+                    };
+                    Ok(SoftTimer(i))
+                }
+                None => Err(Error { _0: () }),
+            }
+        })
+    }
+
+    /// Starts the timer, unless it's already running
+    pub fn start(&self) {
+        interrupt::free(|_| unsafe {
+            if !TABLE[self.0].running {
+                self.arm();
+            }
+        })
+    }
+
+    /// Starts the timer over, whether it's running or not
+    pub fn restart(&self) {
+        interrupt::free(|_| unsafe { self.arm() })
+    }
+
+    /// Stops the timer
+    pub fn stop(&self) {
+        interrupt::free(|_| unsafe { TABLE[self.0].running = false })
+    }
+
+    /// Changes the period; takes effect at the next (re)start or expiry
+    ///
+    /// Returns `Err` if `period` is zero
+    pub fn set_period(&self, period: u32) -> Result<()> {
+        if period == 0 {
+            return Err(Error { _0: () });
+        }
+
+        interrupt::free(|_| unsafe { TABLE[self.0].period = period });
+        Ok(())
+    }
+
+    /// Returns `true` if the timer is running
+    pub fn is_running(&self) -> bool {
+        unsafe { TABLE[self.0].running }
+    }
+
+    /// Ticks until the timer expires; `None` if it's stopped
+    pub fn remaining(&self) -> Option<u32> {
+        interrupt::free(|_| unsafe {
+            let entry = &TABLE[self.0];
+            if entry.running {
+                Some(entry.deadline.wrapping_sub(TICKS))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Clears the flag the timer sets when it expires
+    ///
+    /// Returns `Err` if the timer hasn't expired since the last call
+    pub fn clear_flag(&self) -> Result<()> {
+        interrupt::free(|_| unsafe {
+            let entry = &mut TABLE[self.0];
+            if entry.expired {
+                entry.expired = false;
+                Ok(())
+            } else {
+                Err(Error { _0: () })
+            }
+        })
+    }
+
+    /// Releases the timer's entry in the table
+    pub fn delete(self) {
+        interrupt::free(|_| unsafe { TABLE[self.0] = UNUSED })
+    }
+
+    /// Sets the deadline one period from now and marks the timer running
+    ///
+    /// NOTE must be called in a critical section
+    unsafe fn arm(&self) {
+        let entry = &mut TABLE[self.0];
+        entry.deadline = TICKS.wrapping_add(entry.period);
+        entry.running = true;
+    }
+}
+
+/// Counts a tick and fires the timers that expire
+///
+/// Call it from one periodic interrupt, and only one.
+pub fn tick() {
+    let now = interrupt::free(|_| unsafe {
+        TICKS = TICKS.wrapping_add(1);
+        TICKS
+    });
+
+    for i in 0..CAPACITY {
+        let callback = interrupt::free(|_| unsafe {
+            let entry = &mut TABLE[i];
+            if !entry.running || entry.deadline != now {
+                return None;
+            }
+
+            entry.expired = true;
+            match entry.mode {
+                Mode::OneShot => entry.running = false,
+                Mode::Periodic => {
+                    entry.deadline = entry.deadline.wrapping_add(entry.period)
+                }
+            }
+
+            entry.callback
+        });
+
+        // NOTE the callback runs outside the critical section, so it can
+        // (re)start and stop timers
+        if let Some(callback) = callback {
+            callback(&SoftTimer(i));
+        }
+    }
+}
+
+/// Ticks counted by `tick`, wrapping around
+pub fn ticks() -> u32 {
+    unsafe { TICKS }
+}
@@ -0,0 +1,329 @@
+//! Monotonic time base and delays, driven by SysTick
+//!
+//! `init` makes SysTick interrupt every `period` us; its exception handler,
+//! `sys_tick`, counts the ticks in 64 bits. `now` combines that count with
+//! the SysTick counter, so `Instant`s have microsecond resolution whatever
+//! the period, and won't wrap around in the lifetime of the device.
+//!
+//! `sys_tick` is in the exception vector this crate provides (the
+//! "exceptions" feature, on by default). With an exception vector of your
+//! own, put it there.
+//!
+//! `now` can only account for one SysTick wrap around that `sys_tick`
+//! hasn't counted yet: with interrupts masked for longer than a period, e.g.
+//! in an RTFM `init` or a critical section, time stands still and waiting on
+//! a `Deadline` hangs. The delays don't go through `now`, they count the
+//! SysTick cycles themselves, so they work there too. Before `init` they
+//! count core cycles instead, which is less precise and stretches when
+//! interrupts fire.
+
+use core::ops::{Add, AddAssign, Sub};
+
+use cast::u64;
+use cortex_m::asm;
+use cortex_m::exception;
+use cortex_m::interrupt;
+use cortex_m::peripheral::SystClkSource;
+use stm32f100::{SCB, SYST, Syst};
+
+use frequency;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+pub struct Error {
+    _0: (),
+}
+
+/// Default tick period, in us
+pub const DEFAULT_PERIOD: u32 = 1_000;
+
+/// SysTick counts core clock cycles
+pub const CLOCK: u32 = frequency::AHB;
+
+// Core clock cycles per us
+const CYCLES_PER_US: u32 = CLOCK / 1_000_000;
+
+// SysTick has a 24-bit counter
+const MAX_RELOAD: u32 = 0x00FF_FFFF;
+
+// SCB_ICSR: SysTick exception pending
+const PENDSTSET: u32 = 1 << 26;
+
+// Ticks since `init`
+static mut TICKS: u64 = 0;
+
+// Tick period, in us; zero until `init`
+static mut PERIOD: u32 = 0;
+
+/// Called from `sys_tick` on every tick, e.g. `soft_timer::tick`
+pub type TickHandler = fn();
+
+static mut TICK_HANDLER: Option<TickHandler> = None;
+
+/// A span of time, with microsecond resolution
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// `us` microseconds
+    pub fn from_micros(us: u64) -> Duration {
+        Duration(us)
+    }
+
+    /// `ms` milliseconds
+    pub fn from_millis(ms: u64) -> Duration {
+        Duration(ms * 1_000)
+    }
+
+    /// `s` seconds
+    pub fn from_secs(s: u64) -> Duration {
+        Duration(s * 1_000_000)
+    }
+
+    /// Whole microseconds
+    pub fn as_micros(&self) -> u64 {
+        self.0
+    }
+
+    /// Whole milliseconds
+    pub fn as_millis(&self) -> u64 {
+        self.0 / 1_000
+    }
+
+    /// Whole seconds
+    pub fn as_secs(&self) -> u64 {
+        self.0 / 1_000_000
+    }
+
+    /// `self - other`, or zero if `other` is longer
+    pub fn saturating_sub(&self, other: Duration) -> Duration {
+        Duration(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, other: Duration) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, other: Duration) -> Duration {
+        Duration(self.0 - other.0)
+    }
+}
+
+/// A point in time, in microseconds since `init`
+#[derive(Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// Time since `self`
+    pub fn elapsed(&self) -> Duration {
+        now().duration_since(*self)
+    }
+
+    /// Time from `earlier` to `self`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Microseconds since `init`
+    pub fn as_micros(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant(self.0 + duration.0)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        self.0 += duration.0;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        Instant(self.0 - duration.0)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// A point in time to check against without blocking
+#[derive(Clone, Copy, PartialEq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// `timeout` from now
+    pub fn after(timeout: Duration) -> Deadline {
+        Deadline(now() + timeout)
+    }
+
+    /// Returns `true` once the deadline is reached
+    pub fn has_expired(&self) -> bool {
+        now() >= self.0
+    }
+
+    /// Time left until the deadline; zero once it's reached
+    pub fn remaining(&self) -> Duration {
+        self.0.duration_since(now())
+    }
+
+    /// Moves the deadline `period` further, e.g. for periodic work that
+    /// mustn't drift
+    pub fn extend(&mut self, period: Duration) {
+        self.0 += period;
+    }
+}
+
+/// Starts SysTick with a tick every `period` us and resets the time to zero
+///
+/// Returns `Err` if `period` is zero or longer than SysTick can count
+/// (about 2 s at 8 MHz)
+pub fn init(syst: &Syst, period: u32) -> Result<()> {
+    let reload = match period.checked_mul(CYCLES_PER_US) {
+        Some(cycles) if cycles > 0 && cycles - 1 <= MAX_RELOAD => cycles - 1,
+        _ => return Err(Error { _0: () }),
+    };
+
+    syst.disable_counter();
+    interrupt::free(|_| unsafe {
+        TICKS = 0;
+        PERIOD = period;
+    });
+
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(reload);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+
+    Ok(())
+}
+
+/// Returns the current time
+///
+/// Returns the zero `Instant` until `init` is called. NOTE with interrupts
+/// masked it stops advancing after one tick period, see the module docs
+pub fn now() -> Instant {
+    interrupt::free(|_| {
+        // NOTE(unsafe) read only accesses to SysTick and the SCB; `TICKS`
+        // is only written in critical sections
+        let (syst, scb) = unsafe { (&*SYST.get(), &*SCB.get()) };
+        let (ticks, period) = unsafe { (TICKS, PERIOD) };
+
+        if period == 0 {
+            return Instant(0);
+        }
+
+        // The counter may have wrapped around since interrupts were masked,
+        // without `sys_tick` having counted it yet
+        let mut current = syst.get_current();
+        let mut ticks = ticks;
+        if scb.icsr.read() & PENDSTSET != 0 {
+            current = syst.get_current();
+            ticks += 1;
+        }
+
+        let cycles = syst.get_reload() - current;
+        Instant(ticks * u64(period) + u64(cycles / CYCLES_PER_US))
+    })
+}
+
+/// Waits for at least `us` microseconds
+///
+/// Works with interrupts masked too
+pub fn delay_us(us: u32) {
+    if unsafe { PERIOD } == 0 {
+        // NOTE(approximation) a `nop` loop iteration takes about 4 cycles
+        for _ in 0..us * (CYCLES_PER_US / 4) {
+            asm::nop();
+        }
+        return;
+    }
+
+    // NOTE(unsafe) read only accesses to SysTick
+    let syst = unsafe { &*SYST.get() };
+    let reload = syst.get_reload();
+
+    // NOTE polls far more often than the counter wraps around, so every
+    // wrap around is seen, interrupts or not
+    let mut remaining = u64(us) * u64(CYCLES_PER_US);
+    let mut last = syst.get_current();
+    while remaining > 0 {
+        // the counter counts down, from `reload` to zero
+        let current = syst.get_current();
+        let elapsed = if current <= last {
+            last - current
+        } else {
+            last + (reload + 1 - current)
+        };
+
+        remaining = remaining.saturating_sub(u64(elapsed));
+        last = current;
+    }
+}
+
+/// Waits for at least `ms` milliseconds
+pub fn delay_ms(ms: u32) {
+    for _ in 0..ms {
+        delay_us(1_000);
+    }
+}
+
+/// Calls `handler` on every tick, from the SysTick exception
+pub fn listen(handler: TickHandler) {
+    interrupt::free(|_| unsafe { TICK_HANDLER = Some(handler) })
+}
+
+/// Stops calling the handler set with `listen`
+pub fn unlisten() {
+    interrupt::free(|_| unsafe { TICK_HANDLER = None })
+}
+
+/// SysTick exception handler: counts a tick and calls the `listen` handler
+pub extern "C" fn sys_tick(_: exception::SysTick) {
+    interrupt::free(|_| unsafe { TICKS += 1 });
+
+    if let Some(handler) = unsafe { TICK_HANDLER } {
+        handler();
+    }
+}
+
+/// Number of ticks since `init`
+pub fn ticks() -> u64 {
+    interrupt::free(|_| unsafe { TICKS })
+}
+
+/// Tick period, in us; zero if `init` wasn't called
+pub fn period() -> u32 {
+    unsafe { PERIOD }
+}
+
@@ -57,6 +57,13 @@ impl<'a> Timer<'a> {
         }
     }
 
+    /// Makes the update event the trigger output (TRGO) of the timer
+    ///
+    /// Used to pace peripherals like the DAC
+    pub fn trigger_on_update(&self) {
+        self.0.cr2.write(|w| unsafe { w.mms().bits(0b010) });
+    }
+
     /// Resumes the timer count
     pub fn resume(&self) {
         self.0.cr1.modify(|_, w| w.cen().enabled());
@@ -111,6 +118,13 @@ impl<'a> Timer6<'a> {
         }
     }
 
+    /// Makes the update event the trigger output (TRGO) of the timer
+    ///
+    /// Used to pace peripherals like the DAC
+    pub fn trigger_on_update(&self) {
+        self.0.cr2.write(|w| unsafe { w.mms().bits(0b010) });
+    }
+
     /// Resumes the timer count
     pub fn resume(&self) {
         self.0.cr1.modify(|_, w| w.cen().enabled());
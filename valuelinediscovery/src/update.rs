@@ -0,0 +1,258 @@
+//! Dual-slot (A/B) firmware updates, see `../bootloader`
+//!
+//! The flash holds two application slots. The bootloader writes a new image
+//! to the slot that isn't in use, so the previous image stays around until
+//! the new one has proven itself. Each slot has a metadata page:
+//!
+//! - the image version, length and CRC-32, written once the image is in
+//! - a "confirmed" flag, set by the application with `confirm`
+//! - a "rejected" flag, set by the bootloader when it gives up on the image
+//! - a boot attempt counter, bumped by the bootloader every time it starts
+//!   an image that isn't confirmed yet
+//!
+//! The flags and the counter are half-words that go from erased to zero, so
+//! updating them never needs an erase. Each slot has a page of its own, so
+//! erasing the metadata of one slot never puts the other at risk.
+//!
+//! The bootloader starts the IWDG before it starts the application. An
+//! image that doesn't `confirm` itself before it hangs or resets
+//! `MAX_ATTEMPTS` times is rejected, and the bootloader falls back to the
+//! other slot.
+//!
+//! Flash layout:
+//!
+//! - 0x08000000 bootloader (14K)
+//! - 0x08003800 metadata of slot A
+//! - 0x08003C00 metadata of slot B
+//! - 0x08004000 slot A (55K)
+//! - 0x08011C00 slot B (55K)
+//! - 0x0801F800 `eeprom` banks (2K)
+//!
+//! NOTE this layout assumes the 1 KiB pages and 128 KiB flash of the
+//! STM32F100RB
+
+use core::ptr;
+
+use cast::u32;
+use stm32f100::Scb;
+
+use flash::{self, Flash};
+
+/// Size, in bytes, of a slot; the largest image it holds
+pub const SLOT_SIZE: u32 = 55 * 1024;
+
+/// Boots of an image that isn't confirmed before it's rejected
+pub const MAX_ATTEMPTS: u32 = 3;
+
+// Start of slot A; slot B follows it
+const SLOTS: u32 = 0x0800_4000;
+
+// Metadata pages, right before the slots
+const METADATA: u32 = SLOTS - 2 * flash::PAGE_SIZE;
+
+// Written last: the metadata is complete
+const MAGIC: u32 = 0xB007_10AD;
+
+// Offsets into a metadata page
+const VERSION: u32 = 4;
+const LENGTH: u32 = 8;
+const CRC: u32 = 12;
+const CONFIRMED: u32 = 16;
+const REJECTED: u32 = 18;
+const ATTEMPTS: u32 = 20;
+
+// Value of a set flag
+const SET: u16 = 0x0000;
+
+/// An application slot
+#[derive(Clone, Copy, PartialEq)]
+pub enum Slot {
+    /// First slot, at 0x08004000
+    A,
+    /// Second slot, at 0x08011C00
+    B,
+}
+
+/// An image, as recorded in the metadata of its slot
+#[derive(Clone, Copy, PartialEq)]
+pub struct Image {
+    /// Version; each install gets a higher one than what's in the other slot
+    pub version: u32,
+    /// Length, in bytes
+    pub len: u32,
+    /// CRC-32 of the `len` bytes, as computed by the CRC unit
+    pub crc: u32,
+}
+
+impl Slot {
+    /// Start address of the slot: the vector table of its image
+    pub fn address(&self) -> u32 {
+        match *self {
+            Slot::A => SLOTS,
+            Slot::B => SLOTS + SLOT_SIZE,
+        }
+    }
+
+    /// The other slot
+    pub fn other(&self) -> Slot {
+        match *self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    /// Returns `true` if `address` is in the slot
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.address() && address - self.address() < SLOT_SIZE
+    }
+
+    /// Returns the image recorded in the metadata, if any
+    ///
+    /// NOTE this doesn't check the image against its CRC
+    pub fn image(&self) -> Option<Image> {
+        let metadata = self.metadata();
+
+        if word(metadata) != MAGIC {
+            return None;
+        }
+
+        let image = Image {
+            version: word(metadata + VERSION),
+            len: word(metadata + LENGTH),
+            crc: word(metadata + CRC),
+        };
+
+        if image.len > SLOT_SIZE {
+            None
+        } else {
+            Some(image)
+        }
+    }
+
+    /// Returns `true` if the application confirmed the image
+    pub fn is_confirmed(&self) -> bool {
+        half_word(self.metadata() + CONFIRMED) == SET
+    }
+
+    /// Returns `true` if the bootloader gave up on the image
+    pub fn is_rejected(&self) -> bool {
+        half_word(self.metadata() + REJECTED) == SET
+    }
+
+    /// Number of times the image was started without being confirmed
+    pub fn attempts(&self) -> u32 {
+        let metadata = self.metadata();
+
+        u32(
+            (0..MAX_ATTEMPTS)
+                .take_while(|i| half_word(metadata + ATTEMPTS + 2 * i) == SET)
+                .count(),
+        )
+    }
+
+    /// Erases the metadata, which makes the slot empty
+    ///
+    /// The image itself is left alone; the bootloader erases its pages as it
+    /// writes the new one.
+    pub fn erase(&self, flash: Flash) -> flash::Result<()> {
+        flash.erase_page(self.metadata())
+    }
+
+    /// Records `image`, which must already be in the slot, in the erased
+    /// metadata
+    ///
+    /// The image starts out neither confirmed nor rejected.
+    pub fn install(&self, flash: Flash, image: &Image) -> flash::Result<()> {
+        let metadata = self.metadata();
+
+        program_word(flash, metadata + VERSION, image.version)?;
+        program_word(flash, metadata + LENGTH, image.len)?;
+        program_word(flash, metadata + CRC, image.crc)?;
+        program_word(flash, metadata, MAGIC)
+    }
+
+    /// Marks the image as good; the bootloader won't fall back from it
+    pub fn confirm(&self, flash: Flash) -> flash::Result<()> {
+        set(flash, self.metadata() + CONFIRMED)
+    }
+
+    /// Marks the image as bad; the bootloader won't start it again
+    pub fn reject(&self, flash: Flash) -> flash::Result<()> {
+        set(flash, self.metadata() + REJECTED)
+    }
+
+    /// Counts a start of the image
+    ///
+    /// Returns `Err` if all the `MAX_ATTEMPTS` are used up
+    pub fn count_attempt(&self, flash: Flash) -> flash::Result<()> {
+        let attempts = self.attempts();
+
+        if attempts == MAX_ATTEMPTS {
+            Err(flash::Error::Programming)
+        } else {
+            set(flash, self.metadata() + ATTEMPTS + 2 * attempts)
+        }
+    }
+
+    /// Start address of the metadata page
+    fn metadata(&self) -> u32 {
+        match *self {
+            Slot::A => METADATA,
+            Slot::B => METADATA + flash::PAGE_SIZE,
+        }
+    }
+}
+
+/// Returns the slot the running application was started from, according to
+/// `SCB.VTOR`
+///
+/// Returns `None` if it wasn't started by the bootloader, e.g. when it was
+/// flashed with the debugger.
+pub fn running(scb: &Scb) -> Option<Slot> {
+    let vtor = scb.vtor.read();
+
+    if vtor == Slot::A.address() {
+        Some(Slot::A)
+    } else if vtor == Slot::B.address() {
+        Some(Slot::B)
+    } else {
+        None
+    }
+}
+
+/// Confirms the running image
+///
+/// Call it once the application knows it works, e.g. after its self tests
+/// pass, and well before the IWDG started by the bootloader bites.
+///
+/// NOTE the flash must be unlocked
+pub fn confirm(flash: Flash, scb: &Scb) -> flash::Result<()> {
+    match running(scb) {
+        Some(slot) => slot.confirm(flash),
+        None => Ok(()),
+    }
+}
+
+/// Sets the flag at `address`, unless it's already set
+fn set(flash: Flash, address: u32) -> flash::Result<()> {
+    if half_word(address) == SET {
+        Ok(())
+    } else {
+        flash.program(address, SET)
+    }
+}
+
+/// Programs `word` at `address`, low half-word first
+fn program_word(flash: Flash, address: u32, word: u32) -> flash::Result<()> {
+    flash.write(address, &[word as u16, (word >> 16) as u16])
+}
+
+/// Reads the half-word at `address`
+fn half_word(address: u32) -> u16 {
+    unsafe { ptr::read_volatile(address as *const u16) }
+}
+
+/// Reads the word at `address`
+fn word(address: u32) -> u32 {
+    unsafe { ptr::read_volatile(address as *const u32) }
+}
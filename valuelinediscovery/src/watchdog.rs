@@ -0,0 +1,260 @@
+//! Watchdogs
+//!
+//! The independent watchdog (IWDG) runs from the LSI, so it keeps working
+//! even if the main clock fails. Once started it can't be stopped, other
+//! than by a reset.
+//!
+//! The window watchdog (WWDG) runs from PCLK1. Besides resetting the device
+//! when it's not refreshed in time, it also resets it when it's refreshed
+//! too early, which catches code that runs faster than it should, e.g. a
+//! task that skips its work. Its timeouts are short: 262 ms at most.
+
+use cast::{u16, u32, u8};
+use stm32f100::{Dbg, Iwdg, Rcc, Wwdg};
+
+use frequency;
+
+/// Specialized `Result` type
+pub type Result<T> = ::core::result::Result<T, Error>;
+
+/// An error
+#[derive(Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The timeout is zero or too long
+    Timeout,
+    /// The window is not shorter than the timeout
+    Window,
+    /// The window watchdog was refreshed before its window opened
+    Early,
+    /// The early wakeup interrupt hasn't fired
+    NoEarlyWakeup,
+}
+
+/// Fastest LSI frequency, in Hz, from the datasheet (30 - 60 kHz)
+///
+/// The IWDG timeout is computed from this value, so the watchdog never bites
+/// earlier than asked. On a slow LSI it may bite up to twice as late.
+pub const LSI_MAX: u32 = 60_000;
+
+// IWDG_KR keys
+const KEY_RELOAD: u16 = 0xAAAA;
+const KEY_ACCESS: u16 = 0x5555;
+const KEY_START: u16 = 0xCCCC;
+
+/// Longest IWDG timeout, in ms, that `IndependentWatchdog::start` accepts
+pub const MAX_TIMEOUT: u32 = 0x1000 * 256 * 1_000 / LSI_MAX;
+
+// The WWDG resets the device when T6 clears, i.e. when the counter goes from
+// 0x40 to 0x3F
+const T_MIN: u8 = 0x40;
+const T_MAX: u8 = 0x7F;
+
+/// Longest WWDG timeout, in us, that `WindowWatchdog::start` accepts
+pub const MAX_WINDOW_TIMEOUT: u32 =
+    64 * 4096 * 8 / (frequency::APB1 / 1_000_000);
+
+/// Called from `WindowWatchdog::handle_interrupt`, one counter tick before
+/// the reset
+pub type EarlyWakeupHandler = fn();
+
+static mut EARLY_WAKEUP_HANDLER: Option<EarlyWakeupHandler> = None;
+
+// Counter value written by `WindowWatchdog::refresh`
+static mut RELOAD: u8 = T_MAX;
+
+/// Returns `true` if the last reset was caused by the independent watchdog
+///
+/// NOTE The reset flags accumulate until cleared by writing RMVF in RCC_CSR
+pub fn reset_by_independent_watchdog(rcc: &Rcc) -> bool {
+    rcc.csr.read().iwdgrstf().bits() == 1
+}
+
+/// Returns `true` if the last reset was caused by the window watchdog
+pub fn reset_by_window_watchdog(rcc: &Rcc) -> bool {
+    rcc.csr.read().wwdgrstf().bits() == 1
+}
+
+/// Stops the watchdog counters while the core is halted by the debugger
+pub fn freeze_on_debug(dbg: &Dbg) {
+    dbg.cr.modify(
+        |_, w| unsafe { w.dbg_iwdg_stop().bits(1).dbg_wwdg_stop().bits(1) },
+    );
+}
+
+/// Independent watchdog
+#[derive(Clone, Copy)]
+pub struct IndependentWatchdog<'a>(pub &'a Iwdg);
+
+impl<'a> IndependentWatchdog<'a> {
+    /// Starts the watchdog with a timeout of at least `timeout` ms
+    ///
+    /// The LSI is started by hardware. Returns `Err` if `timeout` is zero or
+    /// longer than `MAX_TIMEOUT`
+    pub fn start(&self, timeout: u32) -> Result<()> {
+        let iwdg = self.0;
+
+        if timeout == 0 || timeout > MAX_TIMEOUT {
+            return Err(Error::Timeout);
+        }
+
+        // Smallest prescaler (4 << pr) that makes the reload value fit in
+        // 12 bits. NOTE(round up) a shorter timeout would reset too early
+        let ticks = timeout * (LSI_MAX / 1_000);
+        let periods = |pr: u8| (ticks + (4 << pr) - 1) / (4 << pr);
+        let mut pr = 0;
+        while periods(pr) > 0x1000 {
+            pr += 1;
+        }
+        let rl = u16(periods(pr) - 1).unwrap();
+
+        iwdg.kr.write(|w| unsafe { w.key().bits(KEY_START) });
+
+        iwdg.kr.write(|w| unsafe { w.key().bits(KEY_ACCESS) });
+        while iwdg.sr.read().pvu().bits() == 1 {}
+        iwdg.pr.write(|w| unsafe { w.pr().bits(pr) });
+        while iwdg.sr.read().rvu().bits() == 1 {}
+        iwdg.rlr.write(|w| unsafe { w.rl().bits(rl) });
+
+        // wait for the new values to reach the LSI domain
+        while iwdg.sr.read().bits() != 0 {}
+        self.feed();
+
+        Ok(())
+    }
+
+    /// Reloads the watchdog counter, postponing the reset by one timeout
+    pub fn feed(&self) {
+        self.0.kr.write(|w| unsafe { w.key().bits(KEY_RELOAD) });
+    }
+
+    /// Returns the configured timeout, in ms, at the fastest LSI
+    pub fn timeout(&self) -> u32 {
+        let iwdg = self.0;
+
+        let div = 4 << u32(iwdg.pr.read().pr().bits());
+        let ticks = (u32(iwdg.rlr.read().rl().bits()) + 1) * div;
+
+        ticks / (LSI_MAX / 1_000)
+    }
+}
+
+/// Window watchdog
+///
+/// # Interrupts
+///
+/// - `WwdgIrq` - early wakeup, one counter tick before the reset
+#[derive(Clone, Copy)]
+pub struct WindowWatchdog<'a>(pub &'a Wwdg);
+
+impl<'a> WindowWatchdog<'a> {
+    /// Starts the watchdog with a timeout of at least `timeout` us
+    ///
+    /// Refreshes made less than `window` us after the previous refresh are
+    /// rejected by `refresh`; a `window` of zero accepts them at any time.
+    ///
+    /// Returns `Err` if `timeout` is zero or longer than
+    /// `MAX_WINDOW_TIMEOUT`, or if `window` isn't shorter than `timeout`
+    pub fn start(&self, rcc: &Rcc, timeout: u32, window: u32) -> Result<()> {
+        let wwdg = self.0;
+
+        if timeout == 0 || timeout > MAX_WINDOW_TIMEOUT {
+            return Err(Error::Timeout);
+        }
+
+        // Smallest prescaler (1 << wdgtb) that fits the timeout in the 64
+        // ticks of the counter
+        let mut wdgtb = 0;
+        while ticks(timeout, wdgtb) > 64 {
+            wdgtb += 1;
+        }
+        let t = T_MIN - 1 + u8(ticks(timeout, wdgtb)).unwrap();
+
+        // A refresh is only accepted while the counter is at or below `w`
+        let closed = ticks(window, wdgtb);
+        if window != 0 && closed >= ticks(timeout, wdgtb) {
+            return Err(Error::Window);
+        }
+        let w = if window == 0 { T_MAX } else { t - u8(closed).unwrap() };
+
+        // NOTE(unsafe) the counter isn't refreshed until the watchdog starts
+        unsafe { RELOAD = t }
+
+        rcc.apb1enr.modify(|_, w| w.wwdgen().enabled());
+
+        wwdg.cfr
+            .modify(|_, w| unsafe { w.wdgtb().bits(wdgtb).w().bits(w) });
+        wwdg.cr.write(|w| unsafe { w.t().bits(t).wdga().bits(1) });
+
+        Ok(())
+    }
+
+    /// Reloads the watchdog counter
+    ///
+    /// Returns `Err` if the window hasn't opened yet. The counter is not
+    /// reloaded in that case (the hardware would reset the device right
+    /// away); the watchdog bites at the end of the current timeout unless
+    /// a later refresh lands inside the window.
+    pub fn refresh(&self) -> Result<()> {
+        let wwdg = self.0;
+
+        if wwdg.cr.read().t().bits() > wwdg.cfr.read().w().bits() {
+            Err(Error::Early)
+        } else {
+            // NOTE(unsafe) `RELOAD` is only written before the watchdog starts
+            wwdg.cr.write(|w| unsafe { w.t().bits(RELOAD).wdga().bits(1) });
+            Ok(())
+        }
+    }
+
+    /// Enables the early wakeup interrupt, calling `handler` from
+    /// `handle_interrupt`
+    ///
+    /// NOTE the interrupt can only be disabled by a reset
+    pub fn listen(&self, handler: EarlyWakeupHandler) {
+        // NOTE(unsafe) the interrupt is disabled until the handler is set
+        unsafe { EARLY_WAKEUP_HANDLER = Some(handler) }
+        self.0.cfr.modify(|_, w| unsafe { w.ewi().bits(1) });
+    }
+
+    /// Clears the early wakeup flag
+    ///
+    /// Returns `Err` if the early wakeup hasn't fired
+    pub fn clear_early_wakeup_flag(&self) -> Result<()> {
+        let wwdg = self.0;
+
+        if wwdg.sr.read().ewi().bits() == 0 {
+            Err(Error::NoEarlyWakeup)
+        } else {
+            // NOTE(write) the flag is cleared by writing 0
+            wwdg.sr.write(|w| unsafe { w.ewi().bits(0) });
+            Ok(())
+        }
+    }
+
+    /// Services the early wakeup; call this from the `WwdgIrq` task
+    ///
+    /// If the early wakeup fired, the flag is cleared and the handler passed
+    /// to `listen` is called. It has one counter tick (at least 512 us) to
+    /// save whatever state should survive the reset, e.g. into the backup
+    /// registers, or to `refresh` the watchdog.
+    ///
+    /// Returns `Err` if the early wakeup hasn't fired
+    pub fn handle_interrupt(&self) -> Result<()> {
+        self.clear_early_wakeup_flag()?;
+
+        if let Some(handler) = unsafe { EARLY_WAKEUP_HANDLER } {
+            handler();
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of WWDG counter ticks, rounded up, in `us` microseconds with a
+/// prescaler of `1 << wdgtb`
+fn ticks(us: u32, wdgtb: u8) -> u32 {
+    // NOTE the counter runs at PCLK1 / 4096 / (1 << wdgtb)
+    let tick = (4096 << wdgtb) / (frequency::APB1 / 1_000_000);
+
+    (us + tick - 1) / tick
+}